@@ -0,0 +1,78 @@
+//! False positive rate, memory and speed of each `FilterPolicy`, over keys
+//! shaped like those of `randomdata.py`: 20 random lowercase letters or a
+//! number between 1000 and 9999. Run with `cargo bench --bench filters`.
+
+#[path = "../src/errors.rs"]
+pub mod errors;
+#[path = "../src/kv/mod.rs"]
+// The unit tests of the modules are compiled out of benches, leaving what
+// only they use unused
+#[cfg_attr(test, allow(dead_code, unused_imports))]
+pub mod kv;
+
+use kv::filter::{BloomFilterPolicy, FilterPolicy, RibbonFilterPolicy};
+use rand::distributions::{Alphanumeric, Distribution, Uniform};
+use rand::Rng;
+use std::collections::HashSet;
+use std::time::Instant;
+
+const KEYS: usize = 200_000;
+const PROBES: usize = 200_000;
+
+fn random_key(rng: &mut impl Rng) -> Vec<u8> {
+    if rng.gen_bool(0.5) {
+        rng.gen_range(1000..=9999).to_string().into_bytes()
+    } else {
+        let letters = Uniform::new_inclusive(b'a', b'z');
+        (0..20).map(|_| letters.sample(rng)).collect()
+    }
+}
+
+fn bench(policy: &dyn FilterPolicy, bits_per_key: usize, keys: &[&[u8]], probes: &[Vec<u8>]) {
+    let start = Instant::now();
+    let encoded = policy.build(keys, bits_per_key);
+    let build = start.elapsed();
+    let filter = policy.open(&encoded).expect("a filter just built opens");
+
+    assert!(keys.iter().all(|key| filter.may_contain(key)));
+    let start = Instant::now();
+    let false_positives = probes
+        .iter()
+        .filter(|probe| filter.may_contain(probe))
+        .count();
+    let query = start.elapsed();
+
+    println!(
+        "{:<7} {:>2} bits/key: {:>6.2} bits/key used, {:>6.3}% false positives, build {:>6.1} ms, {:>5.0} ns/query",
+        policy.name(),
+        bits_per_key,
+        filter.memory_usage() as f64 * 8.0 / keys.len() as f64,
+        false_positives as f64 * 100.0 / probes.len() as f64,
+        build.as_secs_f64() * 1000.0,
+        query.as_nanos() as f64 / probes.len() as f64,
+    );
+}
+
+fn main() {
+    let mut rng = rand::thread_rng();
+    // Numbers only span 9000 keys, the rest are words
+    let keys: HashSet<Vec<u8>> = (0..KEYS).map(|_| random_key(&mut rng)).collect();
+    let mut sorted: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+    sorted.sort();
+    // Absent keys, of a shape no stored key has
+    let probes: Vec<Vec<u8>> = (0..PROBES)
+        .map(|_| (&mut rng).sample_iter(Alphanumeric).take(16).collect())
+        .collect();
+
+    println!(
+        "{} distinct keys, {} absent probes",
+        sorted.len(),
+        probes.len()
+    );
+    let policies: [&dyn FilterPolicy; 2] = [&BloomFilterPolicy, &RibbonFilterPolicy];
+    for bits_per_key in [6, 10, 16] {
+        for policy in policies {
+            bench(policy, bits_per_key, &sorted, &probes);
+        }
+    }
+}
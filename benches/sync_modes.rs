@@ -0,0 +1,77 @@
+//! Write throughput of each `SyncMode`, single-threaded and with concurrent
+//! writers. Run with `cargo bench --bench sync_modes`; set `SHORTERDB_BENCH_DIR`
+//! to benchmark on a disk other than the temp directory's.
+
+#[path = "../src/errors.rs"]
+pub mod errors;
+#[path = "../src/kv/mod.rs"]
+// The unit tests of the modules are compiled out of benches, leaving what
+// only they use unused
+#[cfg_attr(test, allow(dead_code, unused_imports))]
+pub mod kv;
+
+use kv::db::ShorterDB;
+use kv::options::Options;
+use kv::wal::SyncMode;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const WRITES: usize = 4000;
+const VALUE: &[u8] = &[b'x'; 100];
+
+fn bench(name: &str, sync_mode: SyncMode, threads: usize) -> errors::Result<()> {
+    let base = std::env::var_os("SHORTERDB_BENCH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let dir = base.join(format!("shorterdb-bench-{}-{}", name, threads));
+    let _ = std::fs::remove_dir_all(&dir);
+    let options = Options {
+        sync_mode,
+        ..Default::default()
+    };
+    let db = Arc::new(ShorterDB::with_options(&dir, options)?);
+
+    let start = Instant::now();
+    let writers: Vec<_> = (0..threads)
+        .map(|thread| {
+            let db = Arc::clone(&db);
+            std::thread::spawn(move || -> errors::Result<()> {
+                for i in 0..WRITES / threads {
+                    db.set(format!("key-{:02}-{:08}", thread, i).as_bytes(), VALUE)?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("writer panicked")?;
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<14} {:>2} thread(s): {:>10.0} writes/s",
+        name,
+        threads,
+        WRITES as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn main() -> errors::Result<()> {
+    let modes = [
+        ("always", SyncMode::Always),
+        (
+            "periodic-10ms",
+            SyncMode::Periodic(Duration::from_millis(10)),
+        ),
+        ("group-commit", SyncMode::GroupCommit),
+        ("disabled", SyncMode::Disabled),
+    ];
+    for threads in [1, 8] {
+        for (name, sync_mode) in modes {
+            bench(name, sync_mode, threads)?;
+        }
+    }
+    Ok(())
+}
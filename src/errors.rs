@@ -1,5 +1,6 @@
 // use serde_json;
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// Error type for kvs.
@@ -23,6 +24,26 @@ pub enum ShortDBErrors {
     ValueNotSet,
     #[error("We need to flush to sst, Max size for Memtable reached")]
     FlushNeededFromMemTable,
+    /// A key the transaction read for update or wrote was written by
+    /// another transaction since it began; retry the transaction.
+    #[error("Transaction conflict")]
+    TransactionConflict,
+    /// Waiting for the lock on a key would have deadlocked: its holder
+    /// waits, directly or not, for a key the transaction holds. Roll the
+    /// transaction back and retry it.
+    #[error("Deadlock detected while waiting for a lock")]
+    Deadlock,
+    /// A key stayed locked by another transaction for longer than the lock
+    /// timeout.
+    #[error("Timed out waiting for a lock")]
+    LockTimeout,
+    /// A block of the SST `file` starting at `offset` failed its checksum
+    /// or couldn't be decoded.
+    #[error("Corrupted SST {file:?} at offset {offset}")]
+    Corruption { file: PathBuf, offset: u64 },
+    /// The database was closed.
+    #[error("Database is closed")]
+    Closed,
 }
 
 /// Result type for kvs.
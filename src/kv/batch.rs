@@ -0,0 +1,47 @@
+use super::value::Value;
+use bytes::Bytes;
+
+/// Puts and deletes applied atomically by `ShorterDB::write`: after a crash
+/// either all of them are recovered or none, and readers never see only
+/// part of them.
+#[derive(Clone, Default)]
+pub struct WriteBatch {
+    entries: Vec<(Bytes, Value)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.entries.push((
+            Bytes::copy_from_slice(key),
+            Value::Put(Bytes::copy_from_slice(value)),
+        ));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.entries
+            .push((Bytes::copy_from_slice(key), Value::Delete));
+    }
+
+    /// Number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The writes in the order they were added; where several write the
+    /// same key, the last one wins.
+    pub fn entries(&self) -> &[(Bytes, Value)] {
+        &self.entries
+    }
+}
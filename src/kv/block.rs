@@ -0,0 +1,319 @@
+use super::{key::InternalKey, value::Value};
+use bytes::Bytes;
+
+const KIND_PUT: u8 = 0;
+const KIND_DELETE: u8 = 1;
+
+/// Builds a data block of an SST: entries sorted by internal key, each user
+/// key stored as the length it shares with the previous one plus the rest.
+/// Every `restart_interval` entries a restart point stores its key in full,
+/// so a lookup can binary search the restart points and only decode the
+/// entries after one of them.
+///
+/// An entry is `[shared: varint][unshared: varint][unshared key bytes]
+/// [seq: u64][kind: u8]`, followed by `[value length: varint][value]` for a
+/// put. The block ends with the offsets of its restart points (`u32` each)
+/// and their number (`u32`).
+pub struct BlockBuilder {
+    buffer: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    /// Entries since the last restart point.
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            restarts: Vec::new(),
+            restart_interval: restart_interval.max(1),
+            counter: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Appends an entry; entries must be added in internal key order.
+    pub fn add(&mut self, key: &InternalKey, value: &Value) {
+        let shared = if self.counter.is_multiple_of(self.restart_interval) {
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+            0
+        } else {
+            self.last_key
+                .iter()
+                .zip(key.user_key.iter())
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        put_varint(&mut self.buffer, shared as u64);
+        put_varint(&mut self.buffer, (key.user_key.len() - shared) as u64);
+        self.buffer.extend_from_slice(&key.user_key[shared..]);
+        self.buffer.extend_from_slice(&key.seq.to_le_bytes());
+        match value {
+            Value::Put(value) => {
+                self.buffer.push(KIND_PUT);
+                put_varint(&mut self.buffer, value.len() as u64);
+                self.buffer.extend_from_slice(value);
+            }
+            Value::Delete => self.buffer.push(KIND_DELETE),
+        }
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(&key.user_key);
+        self.counter += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Size of the block if it were finished now.
+    pub fn estimated_size(&self) -> usize {
+        self.buffer.len() + 4 * (self.restarts.len() + 1)
+    }
+
+    /// Returns the encoded block and starts a new one.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buffer);
+        for restart in &self.restarts {
+            block.extend_from_slice(&restart.to_le_bytes());
+        }
+        block.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+        self.restarts.clear();
+        self.counter = 0;
+        self.last_key.clear();
+        block
+    }
+}
+
+/// A data block read back from an SST.
+pub struct Block<'a> {
+    /// The entries, without the restart array.
+    entries: &'a [u8],
+    restarts: &'a [u8],
+}
+
+impl<'a> Block<'a> {
+    /// Splits an encoded block, `None` if it is malformed.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let count_at = data.len().checked_sub(4)?;
+        let count = u32::from_le_bytes(data[count_at..].try_into().unwrap()) as usize;
+        let restarts_at = count_at.checked_sub(count.checked_mul(4)?)?;
+        Some(Self {
+            entries: &data[..restarts_at],
+            restarts: &data[restarts_at..count_at],
+        })
+    }
+
+    /// Iterates over every entry of the block.
+    pub fn iter(&self) -> BlockIter<'a> {
+        BlockIter {
+            data: self.entries,
+            position: 0,
+            key: Vec::new(),
+            malformed: false,
+        }
+    }
+
+    /// Iterates from the last restart point before the versions of
+    /// `user_key`: every entry after it is at or after them.
+    pub fn seek(&self, user_key: &[u8]) -> BlockIter<'a> {
+        let restarts: Vec<usize> = self
+            .restarts
+            .chunks_exact(4)
+            .map(|offset| u32::from_le_bytes(offset.try_into().unwrap()) as usize)
+            .collect();
+        // A restart point equal to `user_key` may come after its newest
+        // versions
+        let before = restarts
+            .partition_point(|&offset| self.restart_key(offset).is_some_and(|key| key < user_key));
+        BlockIter {
+            data: self.entries,
+            position: before.checked_sub(1).map_or(0, |restart| restarts[restart]),
+            key: Vec::new(),
+            malformed: false,
+        }
+    }
+
+    /// The full key stored at a restart point.
+    fn restart_key(&self, offset: usize) -> Option<&'a [u8]> {
+        let mut data = self.entries.get(offset..)?;
+        let _shared = get_varint(&mut data)?;
+        let unshared = get_varint(&mut data)? as usize;
+        data.get(..unshared)
+    }
+}
+
+/// Decodes the entries of a block in order. Stops early at a malformed
+/// entry, see `is_malformed`.
+pub struct BlockIter<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// User key of the previous entry, which the next one shares a prefix
+    /// with.
+    key: Vec<u8>,
+    malformed: bool,
+}
+
+impl BlockIter<'_> {
+    /// Whether iterating stopped at an entry that couldn't be decoded
+    /// rather than at the end of the block.
+    pub fn is_malformed(&self) -> bool {
+        self.malformed
+    }
+}
+
+impl BlockIter<'_> {
+    fn decode(&mut self) -> Option<(InternalKey, Value)> {
+        let mut data = self.data.get(self.position..)?;
+        let start = data.len();
+        let shared = get_varint(&mut data)? as usize;
+        let unshared = get_varint(&mut data)? as usize;
+        if shared > self.key.len() {
+            return None;
+        }
+        self.key.truncate(shared);
+        self.key.extend_from_slice(data.get(..unshared)?);
+        data = &data[unshared..];
+        let seq = u64::from_le_bytes(data.get(..8)?.try_into().unwrap());
+        let kind = *data.get(8)?;
+        data = &data[9..];
+        let value = match kind {
+            KIND_PUT => {
+                let len = get_varint(&mut data)? as usize;
+                let value = Bytes::copy_from_slice(data.get(..len)?);
+                data = &data[len..];
+                Value::Put(value)
+            }
+            KIND_DELETE => Value::Delete,
+            _ => return None,
+        };
+        self.position += start - data.len();
+        Some((InternalKey::new(&self.key, seq), value))
+    }
+}
+
+impl Iterator for BlockIter<'_> {
+    type Item = (InternalKey, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.decode();
+        if entry.is_none() {
+            self.malformed |= self.position < self.data.len();
+            self.position = self.data.len();
+        }
+        entry
+    }
+}
+
+/// Appends `value` as a LEB128 varint.
+pub fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Reads a LEB128 varint off the front of `data`.
+pub fn get_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<(InternalKey, Value)> {
+        let mut entries = Vec::new();
+        for i in 0..40 {
+            let key = format!("key-{:03}", i);
+            // Two versions of every key, newest first
+            entries.push((InternalKey::new(key.as_bytes(), 2), Value::Delete));
+            entries.push((
+                InternalKey::new(key.as_bytes(), 1),
+                Value::Put(Bytes::from(format!("value-{}", i))),
+            ));
+        }
+        entries
+    }
+
+    fn build(entries: &[(InternalKey, Value)], restart_interval: usize) -> Vec<u8> {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for (key, value) in entries {
+            builder.add(key, value);
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn iterates_what_was_added() {
+        let entries = entries();
+        for restart_interval in [1, 3, 16, 1000] {
+            let encoded = build(&entries, restart_interval);
+            let block = Block::new(&encoded).unwrap();
+            let mut iter = block.iter();
+            assert_eq!(iter.by_ref().collect::<Vec<_>>(), entries);
+            assert!(!iter.is_malformed());
+        }
+    }
+
+    #[test]
+    fn seek_starts_before_every_version_of_the_key() {
+        let entries = entries();
+        let encoded = build(&entries, 4);
+        let block = Block::new(&encoded).unwrap();
+        for i in [0, 1, 17, 39] {
+            let key = format!("key-{:03}", i);
+            let found: Vec<_> = block
+                .seek(key.as_bytes())
+                .filter(|(found, _)| found.user_key == key.as_bytes())
+                .collect();
+            assert_eq!(found, entries[2 * i..2 * i + 2]);
+        }
+    }
+
+    #[test]
+    fn truncated_block_is_malformed() {
+        let encoded = build(&entries(), 4);
+        assert!(Block::new(&encoded[..2]).is_none());
+
+        // Restarts claiming more of the block than there is
+        let mut bogus = encoded.clone();
+        let len = bogus.len();
+        bogus[len - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Block::new(&bogus).is_none());
+
+        // An entry cut in the middle stops iterating early
+        let block = Block::new(&encoded).unwrap();
+        let cut = Block {
+            entries: &block.entries[..block.entries.len() - 3],
+            restarts: block.restarts,
+        };
+        let mut iter = cut.iter();
+        assert_eq!(iter.by_ref().count(), entries().len() - 1);
+        assert!(iter.is_malformed());
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buffer = Vec::new();
+            put_varint(&mut buffer, value);
+            let mut data = buffer.as_slice();
+            assert_eq!(get_varint(&mut data), Some(value));
+            assert!(data.is_empty());
+        }
+        assert_eq!(get_varint(&mut &[0x80][..]), None);
+    }
+}
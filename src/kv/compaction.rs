@@ -0,0 +1,765 @@
+use super::{key::InternalKey, levels::Table, manifest::FileMeta, sst::TableScan, value::Value};
+use crate::errors::Result;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+pub const NUM_LEVELS: usize = 7;
+/// Compaction output is split into files of roughly this size.
+pub const TARGET_FILE_SIZE: u64 = 256 * 1024; // 256 KB
+
+/// A set of input files to merge into `output_level`.
+pub struct Compaction {
+    pub output_level: usize,
+    pub inputs: Vec<Table>,
+    /// Output is split into files of about this many bytes.
+    pub target_file_size: u64,
+}
+
+/// Bytes written to SST files since the database was opened.
+#[derive(Clone, Debug, Default)]
+pub struct CompactionStats {
+    pub strategy: &'static str,
+    pub compactions: u64,
+    /// Bytes written by memtable flushes, i.e. what users wrote.
+    pub bytes_flushed: u64,
+    /// Bytes rewritten by compactions.
+    pub bytes_compacted: u64,
+}
+
+impl CompactionStats {
+    /// Bytes written to SST files for every byte flushed.
+    pub fn write_amplification(&self) -> f64 {
+        if self.bytes_flushed == 0 {
+            return 0.0;
+        }
+        (self.bytes_flushed + self.bytes_compacted) as f64 / self.bytes_flushed as f64
+    }
+}
+
+/// Decides which SST files get merged together, and when.
+///
+/// Whatever a strategy picks, files in L0 may overlap and are ordered by
+/// their seq, while files in L1 and below never overlap within a level and
+/// hold older data than the levels above them.
+pub trait CompactionStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Picks the most urgent compaction, `None` if nothing needs compacting.
+    fn pick(&self, tables: &[Table]) -> Option<Compaction>;
+
+    /// Picks the compaction of the files in `level` that overlap
+    /// `[start, end]`. Called for every level from the top down; returning a
+    /// compaction whose output is `level` itself ends the manual compaction.
+    fn pick_range(
+        &self,
+        tables: &[Table],
+        level: usize,
+        start: &[u8],
+        end: &[u8],
+    ) -> Option<Compaction>;
+}
+
+fn overlaps(meta: &FileMeta, smallest: &[u8], largest: &[u8]) -> bool {
+    meta.smallest.as_slice() <= largest && smallest <= meta.largest.as_slice()
+}
+
+fn level_files(tables: &[Table], level: usize) -> impl Iterator<Item = &Table> {
+    tables.iter().filter(move |table| table.meta.level == level)
+}
+
+/// Leveled compaction: L0 is merged into L1 once it holds enough files,
+/// and every following level is kept under a size limit that grows by
+/// `level_size_multiplier` per level by pushing files one level down.
+///
+/// Reads touch few files and space overhead stays low, at the price of
+/// rewriting data once per level.
+pub struct LeveledCompaction {
+    /// Number of L0 files that triggers an L0 -> L1 compaction.
+    pub l0_compaction_trigger: usize,
+    /// Size limit of L1.
+    pub max_bytes_for_level_base: u64,
+    pub level_size_multiplier: u64,
+}
+
+impl Default for LeveledCompaction {
+    fn default() -> Self {
+        Self {
+            l0_compaction_trigger: 4,
+            max_bytes_for_level_base: 1024 * 1024, // 1 MB
+            level_size_multiplier: 10,
+        }
+    }
+}
+
+impl LeveledCompaction {
+    pub fn max_bytes_for_level(&self, level: usize) -> u64 {
+        self.max_bytes_for_level_base
+            * self
+                .level_size_multiplier
+                .pow(level.saturating_sub(1) as u32)
+    }
+
+    /// Adds the files of the level below that overlap `inputs`.
+    fn with_next_level(tables: &[Table], level: usize, mut inputs: Vec<Table>) -> Compaction {
+        let smallest = inputs
+            .iter()
+            .map(|t| t.meta.smallest.clone())
+            .min()
+            .unwrap();
+        let largest = inputs.iter().map(|t| t.meta.largest.clone()).max().unwrap();
+        inputs.extend(
+            level_files(tables, level + 1)
+                .filter(|table| overlaps(&table.meta, &smallest, &largest))
+                .cloned(),
+        );
+        Compaction {
+            output_level: level + 1,
+            inputs,
+            target_file_size: TARGET_FILE_SIZE,
+        }
+    }
+}
+
+impl CompactionStrategy for LeveledCompaction {
+    fn name(&self) -> &'static str {
+        "leveled"
+    }
+
+    /// L0 is compacted into L1 once it holds `l0_compaction_trigger` files,
+    /// otherwise the level that exceeds its size limit the most pushes its
+    /// oldest file one level down.
+    fn pick(&self, tables: &[Table]) -> Option<Compaction> {
+        let l0: Vec<Table> = level_files(tables, 0).cloned().collect();
+        if l0.len() >= self.l0_compaction_trigger {
+            return Some(Self::with_next_level(tables, 0, l0));
+        }
+
+        let mut most_urgent: Option<(f64, usize)> = None;
+        for level in 1..NUM_LEVELS - 1 {
+            let size: u64 = level_files(tables, level)
+                .map(|table| table.meta.size)
+                .sum();
+            let score = size as f64 / self.max_bytes_for_level(level) as f64;
+            if score > 1.0 && most_urgent.is_none_or(|(best, _)| score > best) {
+                most_urgent = Some((score, level));
+            }
+        }
+
+        let (_, level) = most_urgent?;
+        let oldest = level_files(tables, level).min_by_key(|table| table.meta.seq)?;
+        Some(Self::with_next_level(tables, level, vec![oldest.clone()]))
+    }
+
+    /// Pushes the overlapping files one level down, until nothing overlaps
+    /// the range below them; there they are rewritten in place.
+    ///
+    /// Files in L0 overlap each other, so an L0 compaction always takes all
+    /// of them; leaving an older version behind in L0 would shadow the newer
+    /// one moved to L1.
+    fn pick_range(
+        &self,
+        tables: &[Table],
+        level: usize,
+        start: &[u8],
+        end: &[u8],
+    ) -> Option<Compaction> {
+        let inputs: Vec<Table> = level_files(tables, level)
+            .filter(|table| level == 0 || overlaps(&table.meta, start, end))
+            .cloned()
+            .collect();
+        if inputs.is_empty() {
+            return None;
+        }
+
+        let deeper = tables
+            .iter()
+            .any(|table| table.meta.level > level && overlaps(&table.meta, start, end));
+        if level > 0 && !deeper {
+            return Some(Compaction {
+                output_level: level,
+                inputs,
+                target_file_size: TARGET_FILE_SIZE,
+            });
+        }
+        Some(Self::with_next_level(tables, level, inputs))
+    }
+}
+
+/// Size-tiered compaction: every file stays in L0, and once enough files
+/// of similar size pile up they are merged into one bigger file.
+///
+/// Data is rewritten only about once per tier, which suits write heavy
+/// ingest, at the price of more files to search and more space held by
+/// overwritten values.
+pub struct SizeTieredCompaction {
+    /// Number of similarly sized files needed to trigger a compaction.
+    pub min_threshold: usize,
+    /// Most files merged by a single compaction.
+    pub max_threshold: usize,
+    /// A file belongs to a tier when its size lies within
+    /// `[bucket_low, bucket_high]` times the average size of the tier.
+    pub bucket_low: f64,
+    pub bucket_high: f64,
+}
+
+impl Default for SizeTieredCompaction {
+    fn default() -> Self {
+        Self {
+            min_threshold: 4,
+            max_threshold: 32,
+            bucket_low: 0.5,
+            bucket_high: 1.5,
+        }
+    }
+}
+
+impl SizeTieredCompaction {
+    fn merge_in_place(inputs: Vec<Table>) -> Compaction {
+        Compaction {
+            output_level: 0,
+            inputs,
+            target_file_size: u64::MAX, // a tier is always a single file
+        }
+    }
+}
+
+impl CompactionStrategy for SizeTieredCompaction {
+    fn name(&self) -> &'static str {
+        "size-tiered"
+    }
+
+    /// Groups L0 files of similar size into tiers and merges the tier of
+    /// smallest files that reached `min_threshold`.
+    ///
+    /// Files are only grouped with their neighbours in age: merging files
+    /// around a newer one would let the merged, older, versions shadow it.
+    fn pick(&self, tables: &[Table]) -> Option<Compaction> {
+        let mut files: Vec<&Table> = level_files(tables, 0).collect();
+        files.sort_by_key(|table| table.meta.seq);
+
+        let mut tiers: Vec<Vec<&Table>> = Vec::new();
+        let mut current: Vec<&Table> = Vec::new();
+        let mut total = 0;
+        for table in files {
+            let average = total as f64 / current.len().max(1) as f64;
+            let size = table.meta.size as f64;
+            let similar = size >= average * self.bucket_low && size <= average * self.bucket_high;
+            if !current.is_empty() && !similar {
+                tiers.push(std::mem::take(&mut current));
+                total = 0;
+            }
+            total += table.meta.size;
+            current.push(table);
+        }
+        tiers.push(current);
+
+        let tier = tiers
+            .into_iter()
+            .filter(|tier| tier.len() >= self.min_threshold)
+            .min_by_key(|tier| tier.iter().map(|t| t.meta.size).sum::<u64>() / tier.len() as u64)?;
+        let inputs = tier.into_iter().take(self.max_threshold).cloned().collect();
+        Some(Self::merge_in_place(inputs))
+    }
+
+    /// Merges every L0 file from the oldest to the newest one overlapping
+    /// `[start, end]` into a single file.
+    fn pick_range(
+        &self,
+        tables: &[Table],
+        level: usize,
+        start: &[u8],
+        end: &[u8],
+    ) -> Option<Compaction> {
+        if level != 0 {
+            return None;
+        }
+        let mut files: Vec<&Table> = level_files(tables, 0).collect();
+        files.sort_by_key(|table| table.meta.seq);
+        let first = files.iter().position(|t| overlaps(&t.meta, start, end))?;
+        let last = files.iter().rposition(|t| overlaps(&t.meta, start, end))?;
+        let inputs = files[first..=last].iter().map(|&t| t.clone()).collect();
+        Some(Self::merge_in_place(inputs))
+    }
+}
+
+/// Merges the inputs into sorted entries holding, of every key, the newest
+/// version and the newest version each snapshot in `snapshots` (ascending
+/// sequence numbers) reads; the versions in between are visible to no one.
+///
+/// `is_bottommost` tells whether no older data for a key may exist outside
+/// the inputs; where it can't, tombstones with no older version kept below
+/// them have nothing left to hide and are dropped.
+///
+/// The inputs are read a data block at a time as the merge advances, which
+/// yields the versions kept of one key at a time. A damaged input table
+/// fails the merge once it is reached, leaving the inputs in place.
+pub fn merge<F>(inputs: &[Table], snapshots: &[u64], is_bottommost: F) -> Result<Merge<F>>
+where
+    F: Fn(&[u8]) -> bool,
+{
+    // Newest first so that on equal sequence numbers, as in tables written
+    // before writes had one, the newer table wins: deeper levels hold older
+    // data, and within L0 a larger seq is newer.
+    let mut inputs: Vec<&Table> = inputs.iter().collect();
+    inputs.sort_by(|a, b| {
+        a.meta
+            .level
+            .cmp(&b.meta.level)
+            .then(b.meta.seq.cmp(&a.meta.seq))
+    });
+
+    let mut merge = Merge {
+        scans: Vec::new(),
+        heads: BinaryHeap::new(),
+        snapshots: snapshots.to_vec(),
+        is_bottommost,
+        failed: false,
+    };
+    for table in inputs {
+        merge.scans.push(table.sst.verified_scan()?);
+        merge.advance(merge.scans.len() - 1)?;
+    }
+    Ok(merge)
+}
+
+/// The next entry of an input of a merge. Heads order by internal key, then
+/// newest input first.
+struct Head {
+    key: InternalKey,
+    value: Value,
+    input: usize,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key).then(self.input.cmp(&other.input))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Merges the inputs of a compaction, see `merge`.
+pub struct Merge<F> {
+    /// Scans of the inputs, newest first.
+    scans: Vec<TableScan>,
+    /// The next entry of every input not exhausted yet, smallest on top.
+    heads: BinaryHeap<Reverse<Head>>,
+    snapshots: Vec<u64>,
+    is_bottommost: F,
+    failed: bool,
+}
+
+impl<F: Fn(&[u8]) -> bool> Merge<F> {
+    /// Reads the next entry of `input` into the heads.
+    fn advance(&mut self, input: usize) -> Result<()> {
+        if let Some(entry) = self.scans[input].next() {
+            let (key, value) = entry?;
+            self.heads.push(Reverse(Head { key, value, input }));
+        }
+        Ok(())
+    }
+
+    /// Takes every version of the next key off the inputs, newest first,
+    /// `None` once they are exhausted.
+    fn next_key(&mut self) -> Result<Option<Vec<(InternalKey, Value)>>> {
+        let Some(Reverse(first)) = self.heads.pop() else {
+            return Ok(None);
+        };
+        self.advance(first.input)?;
+        let mut versions = vec![(first.key, first.value)];
+        while let Some(Reverse(head)) = self.heads.peek() {
+            if head.key.user_key != versions[0].0.user_key {
+                break;
+            }
+            let Reverse(head) = self.heads.pop().unwrap();
+            self.advance(head.input)?;
+            versions.push((head.key, head.value));
+        }
+        Ok(Some(versions))
+    }
+
+    /// Of the versions of a key, the ones still read.
+    fn kept(&self, versions: Vec<(InternalKey, Value)>) -> Vec<(InternalKey, Value)> {
+        // Of the versions between two snapshots, only the newest is read
+        let mut kept: Vec<(InternalKey, Value)> = Vec::new();
+        let mut last_stripe = None;
+        for (key, value) in versions {
+            let stripe = self
+                .snapshots
+                .partition_point(|&snapshot| snapshot < key.seq);
+            if last_stripe != Some(stripe) {
+                last_stripe = Some(stripe);
+                kept.push((key, value));
+            }
+        }
+
+        while kept
+            .last()
+            .is_some_and(|(key, value)| value.is_delete() && (self.is_bottommost)(&key.user_key))
+        {
+            kept.pop();
+        }
+        kept
+    }
+}
+
+impl<F: Fn(&[u8]) -> bool> Iterator for Merge<F> {
+    type Item = Result<Vec<(InternalKey, Value)>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            match self.next_key() {
+                Ok(Some(versions)) => {
+                    let kept = self.kept(versions);
+                    if !kept.is_empty() {
+                        return Some(Ok(kept));
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Groups the keys a merge yields into chunks of about `target_file_size`
+/// bytes, one per output file, as they come.
+///
+/// The versions of a key always end up in the same file, so files of a
+/// level never overlap.
+pub fn split<I>(
+    mut keys: I,
+    target_file_size: u64,
+) -> impl Iterator<Item = Result<Vec<(InternalKey, Value)>>>
+where
+    I: Iterator<Item = Result<Vec<(InternalKey, Value)>>>,
+{
+    std::iter::from_fn(move || {
+        let mut chunk = Vec::new();
+        let mut size = 0;
+        while size < target_file_size {
+            let Some(versions) = keys.next() else {
+                break;
+            };
+            let versions = match versions {
+                Ok(versions) => versions,
+                Err(e) => return Some(Err(e)),
+            };
+            for (key, value) in versions {
+                size += (key.user_key.len() + value.size()) as u64;
+                chunk.push((key, value));
+            }
+        }
+        (!chunk.is_empty()).then_some(Ok(chunk))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ShortDBErrors;
+    use crate::kv::db::ShorterDB;
+    use crate::kv::options::Options;
+    use crate::kv::sst::{TableOptions, SST};
+    use crate::kv::testing::test_dir;
+    use bytes::Bytes;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    /// An SST at `level` holding `entries`, which are in key order; `None`
+    /// is a tombstone.
+    fn table(dir: &Path, id: u64, level: usize, entries: &[(&str, u64, Option<&str>)]) -> Table {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|&(key, seq, value)| {
+                let value = value.map_or(Value::Delete, |value| {
+                    Value::Put(Bytes::copy_from_slice(value.as_bytes()))
+                });
+                (InternalKey::new(key.as_bytes(), seq), value)
+            })
+            .collect();
+        let path = dir.join(format!("{:06}.sst", id));
+        let sst = SST::create(&path, entries.clone(), &TableOptions::default()).unwrap();
+        Table {
+            meta: FileMeta {
+                id,
+                level,
+                smallest: entries[0].0.user_key.to_vec(),
+                largest: entries[entries.len() - 1].0.user_key.to_vec(),
+                seq: id,
+                size: sst.size().unwrap(),
+            },
+            sst: Arc::new(sst),
+        }
+    }
+
+    /// Every entry `merge` keeps.
+    fn merge_all<F>(
+        inputs: &[Table],
+        snapshots: &[u64],
+        is_bottommost: F,
+    ) -> Vec<(InternalKey, Value)>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let merged = merge(inputs, snapshots, is_bottommost).unwrap();
+        merged.flat_map(Result::unwrap).collect()
+    }
+
+    fn versions(merged: &[(InternalKey, Value)]) -> Vec<(&[u8], u64, bool)> {
+        merged
+            .iter()
+            .map(|(key, value)| (key.user_key.as_ref(), key.seq, value.is_delete()))
+            .collect()
+    }
+
+    #[test]
+    fn tombstones_are_dropped_at_the_bottommost_level_only() {
+        let dir = test_dir("merge-tombstones");
+        let inputs = [
+            table(&dir, 2, 1, &[("a", 5, None), ("b", 6, None)]),
+            table(&dir, 1, 2, &[("a", 3, Some("old")), ("c", 4, Some("kept"))]),
+        ];
+
+        // The tombstones hide nothing once no older data is left below them
+        let merged = merge_all(&inputs, &[], |_| true);
+        assert_eq!(versions(&merged), [(&b"c"[..], 4, false)]);
+
+        // Deeper levels may still hold versions they hide
+        let merged = merge_all(&inputs, &[], |key| key != b"b");
+        assert_eq!(
+            versions(&merged),
+            [(&b"b"[..], 6, true), (&b"c"[..], 4, false)]
+        );
+
+        // A snapshot still reads the version under the tombstone
+        let merged = merge_all(&inputs, &[4], |_| true);
+        assert_eq!(
+            versions(&merged),
+            [
+                (&b"a"[..], 5, true),
+                (&b"a"[..], 3, false),
+                (&b"c"[..], 4, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_newest_version_each_snapshot_reads() {
+        let dir = test_dir("merge-snapshots");
+        let inputs = [
+            table(
+                &dir,
+                2,
+                0,
+                &[("k", 6, Some("6")), ("k", 5, None), ("k", 4, Some("4"))],
+            ),
+            table(
+                &dir,
+                1,
+                0,
+                &[
+                    ("k", 3, Some("3")),
+                    ("k", 2, Some("2")),
+                    ("k", 1, Some("1")),
+                ],
+            ),
+        ];
+
+        // Without snapshots only the latest version is read
+        let merged = merge_all(&inputs, &[], |_| false);
+        assert_eq!(versions(&merged), [(&b"k"[..], 6, false)]);
+
+        // Snapshots at 2 and 5 read 2 and the tombstone at 5
+        let merged = merge_all(&inputs, &[2, 5], |_| false);
+        assert_eq!(
+            versions(&merged),
+            [
+                (&b"k"[..], 6, false),
+                (&b"k"[..], 5, true),
+                (&b"k"[..], 2, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn interleaves_the_inputs_a_key_at_a_time() {
+        let dir = test_dir("merge-interleave");
+        let inputs = [
+            table(&dir, 3, 0, &[("b", 0, Some("new")), ("d", 7, Some("d"))]),
+            table(&dir, 2, 0, &[("a", 2, Some("a")), ("c", 5, Some("c"))]),
+            table(&dir, 1, 1, &[("b", 0, Some("old")), ("e", 1, None)]),
+        ];
+        let keys: Vec<_> = merge(&inputs, &[], |_| false)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let merged: Vec<_> = keys.iter().map(|versions| versions[0].clone()).collect();
+        assert!(keys.iter().all(|versions| versions.len() == 1));
+        assert_eq!(
+            versions(&merged),
+            [
+                (&b"a"[..], 2, false),
+                (&b"b"[..], 0, false),
+                (&b"c"[..], 5, false),
+                (&b"d"[..], 7, false),
+                (&b"e"[..], 1, true),
+            ]
+        );
+        // Of versions sharing a sequence number, the newer table's wins
+        assert_eq!(merged[1].1, Value::Put(Bytes::from("new")));
+    }
+
+    #[test]
+    fn damaged_input_fails_the_merge() {
+        let dir = test_dir("merge-damaged");
+        let mut inputs = [
+            table(&dir, 2, 0, &[("a", 2, Some("a")), ("c", 4, Some("c"))]),
+            table(&dir, 1, 1, &[("b", 1, Some("b"))]),
+        ];
+        // A byte of the data block of the older table
+        let path = dir.join("000001.sst");
+        let mut data = fs::read(&path).unwrap();
+        data[10] ^= 0xff;
+        fs::write(&path, data).unwrap();
+        inputs[1].sst = Arc::new(SST::new(&path, &TableOptions::default()).unwrap());
+
+        let merged =
+            merge(&inputs, &[], |_| false).and_then(|merged| merged.collect::<Result<Vec<_>>>());
+        assert!(matches!(merged, Err(ShortDBErrors::Corruption { .. })));
+    }
+
+    #[test]
+    fn split_keeps_the_versions_of_a_key_together() {
+        let entry = |key: &str, seq| {
+            let value = Value::Put(Bytes::from_static(b"value"));
+            (InternalKey::new(key.as_bytes(), seq), value)
+        };
+        let keys = vec![
+            Ok(vec![entry("a", 3), entry("a", 2), entry("a", 1)]),
+            Ok(vec![entry("b", 1)]),
+            Ok(vec![entry("c", 2), entry("c", 1)]),
+        ];
+        // Each version takes 6 bytes
+        let files: Vec<_> = split(keys.into_iter(), 10)
+            .map(|file| versions(&file.unwrap()).len())
+            .collect();
+        assert_eq!(files, [3, 3]);
+    }
+
+    /// L0 files of the given sizes, oldest first and numbered from 1; file
+    /// `n` claims to hold the single key `key-<n>`. Only their metadata is
+    /// looked at.
+    fn l0_files(name: &str, sizes: &[u64]) -> Vec<Table> {
+        let sst = table(&test_dir(name), 1, 0, &[("key", 1, Some("value"))]).sst;
+        sizes
+            .iter()
+            .zip(1..)
+            .map(|(&size, seq)| {
+                let key = format!("key-{}", seq).into_bytes();
+                Table {
+                    meta: FileMeta {
+                        id: seq,
+                        level: 0,
+                        smallest: key.clone(),
+                        largest: key,
+                        seq,
+                        size,
+                    },
+                    sst: Arc::clone(&sst),
+                }
+            })
+            .collect()
+    }
+
+    fn input_seqs(compaction: Option<Compaction>) -> Vec<u64> {
+        let compaction = compaction.expect("a compaction");
+        assert_eq!(compaction.output_level, 0);
+        compaction.inputs.iter().map(|t| t.meta.seq).collect()
+    }
+
+    #[test]
+    fn size_tiers_are_runs_of_files_adjacent_in_age() {
+        let tiered = SizeTieredCompaction::default();
+        let files = l0_files("tiered-adjacent", &[100, 110, 1000, 90, 100, 1000]);
+        // Four small files, but a bigger one lies between them in age
+        assert!(tiered.pick(&files).is_none());
+
+        let files = l0_files(
+            "tiered-smallest",
+            &[1000, 1100, 900, 1000, 100, 110, 90, 100, 5000],
+        );
+        assert_eq!(input_seqs(tiered.pick(&files)), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn size_tier_compaction_takes_at_most_max_threshold_files() {
+        let tiered = SizeTieredCompaction {
+            max_threshold: 5,
+            ..Default::default()
+        };
+        let files = l0_files("tiered-max", &[100; 8]);
+        // The oldest ones
+        assert_eq!(input_seqs(tiered.pick(&files)), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn size_tier_range_compaction_takes_every_file_between() {
+        let tiered = SizeTieredCompaction::default();
+        let files = l0_files("tiered-range", &[100, 1000, 100, 1000, 100]);
+        let picked = tiered.pick_range(&files, 0, b"key-2", b"key-2~");
+        assert_eq!(input_seqs(picked), [2]);
+        // Files 2 and 4 overlap the range, 3 is merged along so no newer
+        // version is left behind an older one
+        let picked = tiered.pick_range(&files, 0, b"key-2", b"key-4");
+        assert_eq!(input_seqs(picked), [2, 3, 4]);
+        assert!(tiered.pick_range(&files, 0, b"key-6", b"key-9").is_none());
+        assert!(tiered.pick_range(&files, 1, b"key-1", b"key-5").is_none());
+    }
+
+    #[test]
+    fn write_amplification_counts_compactions_against_flushes() {
+        let stats = CompactionStats {
+            bytes_flushed: 100,
+            bytes_compacted: 150,
+            ..Default::default()
+        };
+        assert_eq!(stats.write_amplification(), 2.5);
+        assert_eq!(CompactionStats::default().write_amplification(), 0.0);
+
+        let options = Options {
+            compaction_strategy: Arc::new(SizeTieredCompaction::default()),
+            ..Default::default()
+        };
+        let db = ShorterDB::with_options(test_dir("tiered-stats"), options).unwrap();
+        for round in 0..3 {
+            db.set(b"key", round.to_string().as_bytes()).unwrap();
+            db.flush().unwrap();
+        }
+        db.compact_range(b"a", b"z").unwrap();
+        let stats = db.compaction_stats();
+        assert_eq!(stats.strategy, "size-tiered");
+        assert!(stats.bytes_flushed > 0);
+        assert!(stats.bytes_compacted > 0);
+        assert!(stats.write_amplification() > 1.0);
+    }
+}
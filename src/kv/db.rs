@@ -1,121 +1,1213 @@
 use super::{
+    batch::WriteBatch,
+    compaction::CompactionStats,
+    filter::FilterStats,
+    iterator::{to_key_range, DBIterator, Entries, KeyRange},
+    key::MAX_SEQ,
+    levels::Levels,
+    lock::{LockManager, RowLocks},
     memtable::Memtable,
-    sst::SST,
-    wal::{WALEntry, WAL},
+    options::Options,
+    prefix::prefix_range,
+    snapshot::{Snapshot, SnapshotList},
+    transaction::Transaction,
+    wal::{GroupSync, RecoveryReport, SyncMode, WALEntry, WAL},
 };
 use crate::errors::{Result, ShortDBErrors};
 use bytes::Bytes;
-use std::fs;
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use parking_lot::{Mutex, RwLock};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io;
+use std::ops::RangeBounds;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-pub struct ShorterDB {
+/// A full memtable waiting to be flushed. The WAL segments before
+/// `next_wal` back it until it reaches an SST.
+struct ImmutableMemtable {
     memtable: Memtable,
-    wal: WAL,
-    sst: SST,
+    next_wal: u64,
+}
+
+type ImmutableQueue = Arc<RwLock<VecDeque<Arc<ImmutableMemtable>>>>;
+
+/// Asks the flush thread to flush the immutable memtables, with who to tell
+/// once they are in SSTs.
+type FlushRequest = Option<Sender<Result<()>>>;
+
+/// Completes once the flush it was handed out for is done, or has failed.
+struct FlushHandle {
+    done: Receiver<Result<()>>,
+}
+
+impl FlushHandle {
+    /// A handle, and the sender to complete it with.
+    fn new() -> (Sender<Result<()>>, Self) {
+        let (sender, done) = bounded(1);
+        (sender, Self { done })
+    }
+
+    /// Blocks until the flush is done.
+    fn wait(self) -> Result<()> {
+        self.done.recv().unwrap_or_else(|_| {
+            Err(io::Error::other("the flush thread stopped before flushing").into())
+        })
+    }
+}
+
+/// The background threads of an open database, and the channels keeping
+/// them running.
+struct Background {
+    flush_signal: Sender<FlushRequest>,
+    /// Dropped to stop the periodic sync thread.
+    stop_sync: Sender<()>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Background {
+    /// Stops the threads, letting them finish what they were asked to do.
+    fn stop(self) {
+        // The compaction thread follows once the flush thread is gone
+        drop(self.flush_signal);
+        drop(self.stop_sync);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The database. It can be shared between threads as is: reads run
+/// concurrently, writes take turns on the WAL.
+pub struct ShorterDB {
+    memtable: RwLock<Memtable>,
+    immutables: ImmutableQueue,
+    /// Held by writers for the whole write, so they apply one at a time.
+    wal: Arc<Mutex<WAL>>,
+    sync_mode: SyncMode,
+    wal_sync: Arc<GroupSync>,
+    levels: Arc<Levels>,
+    /// Taken by `close`.
+    background: Mutex<Option<Background>>,
+    /// Set by `close` under the WAL lock; writes fail from then on.
+    closed: AtomicBool,
     data_dir: PathBuf,
+    /// Sequence number of the last write visible to readers; every write
+    /// takes the next one.
+    seq: AtomicU64,
+    snapshots: Arc<SnapshotList>,
+    /// Row locks of pessimistic transactions, and of the keys other writes
+    /// are applying.
+    locks: LockManager,
+    recovery: RecoveryReport,
 }
 
 impl ShorterDB {
     pub fn new<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        Self::with_options(data_dir, Options::default())
+    }
+
+    /// Opens the database in `data_dir`, replaying its WAL as
+    /// `options.recovery_mode` says. What recovery left out is kept on the
+    /// database, see `recovery_report`, rather than returned alongside it,
+    /// so opening still returns just the database.
+    pub fn with_options<P: AsRef<Path>>(data_dir: P, options: Options) -> Result<Self> {
+        if options.sync_mode == SyncMode::Periodic(Duration::ZERO) {
+            // The sync thread would never wait between two fsyncs
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SyncMode::Periodic needs a non-zero interval",
+            )
+            .into());
+        }
         let data_dir = data_dir.as_ref().to_path_buf();
         fs::create_dir_all(&data_dir)?; // Ensure the data directory exists
 
-        let wal = WAL::new(&data_dir).unwrap();
-        let sst = SST::new(&data_dir.join("./data.sst"));
+        let snapshots = Arc::new(SnapshotList::default());
+        let levels = Arc::new(Levels::open(&data_dir, &options, Arc::clone(&snapshots))?);
+        let wal = WAL::open(
+            &data_dir,
+            levels.first_live_wal(),
+            options.max_wal_segment_size,
+        )?;
+
+        // Rebuild the memtable from the live WAL segments before accepting
+        // any traffic, otherwise every write that never reached an SST is
+        // lost on restart.
+        let mut seq = levels.max_seq();
+        let mut memtable = Memtable::new();
+        let mut recovery = RecoveryReport::default();
+        let entries = wal.recover(options.recovery_mode, &mut recovery)?;
+        Self::replay_wal(entries, &mut memtable, &mut seq)?;
+        if !recovery.is_clean() {
+            // Damaged segments would be read again on every open, and the
+            // writes left out of them could come back next to newer ones
+            // with the same sequence numbers: persist what was recovered
+            // and retire them.
+            let entries = memtable
+                .memtable
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()));
+            levels.flush(entries, wal.current_segment())?;
+            WAL::remove_segments_before(&data_dir, wal.current_segment())?;
+            memtable = Memtable::new();
+        }
+
+        let immutables = Arc::new(RwLock::new(VecDeque::new()));
+        let mut threads = Vec::new();
+        let (compaction_signal, compaction_requests) = unbounded();
+        threads.push(Self::start_compaction_thread(
+            compaction_requests,
+            Arc::clone(&levels),
+        ));
+        let _ = compaction_signal.send(()); // catch up on a backlog left by a crash
+        let (flush_signal, flush_requests) = unbounded();
+        threads.push(Self::start_flush_thread(
+            flush_requests,
+            data_dir.clone(),
+            Arc::clone(&immutables),
+            Arc::clone(&levels),
+            compaction_signal,
+        ));
+        let wal = Arc::new(Mutex::new(wal));
+        let wal_sync = Arc::new(GroupSync::default());
+        let (stop_sync, sync_stopped) = unbounded();
+        if let SyncMode::Periodic(interval) = options.sync_mode {
+            threads.push(Self::start_sync_thread(
+                interval,
+                sync_stopped,
+                Arc::downgrade(&wal),
+                Arc::clone(&wal_sync),
+            ));
+        }
+
         Ok(Self {
-            memtable: Memtable::new(),
+            memtable: RwLock::new(memtable),
+            immutables,
             wal,
-            sst,
+            sync_mode: options.sync_mode,
+            wal_sync,
+            levels,
+            background: Mutex::new(Some(Background {
+                flush_signal,
+                stop_sync,
+                threads,
+            })),
+            closed: AtomicBool::new(false),
             data_dir,
+            seq: AtomicU64::new(seq),
+            snapshots,
+            locks: LockManager::new(options.lock_timeout),
+            recovery,
         })
     }
 
+    /// What recovering the WAL replayed when the database was opened, and
+    /// what it had to leave out.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Replays `entries` into `memtable`, advancing `last_seq` past them.
+    fn replay_wal(
+        entries: Vec<WALEntry>,
+        memtable: &mut Memtable,
+        last_seq: &mut u64,
+    ) -> Result<()> {
+        for entry in entries {
+            // Entries of logs written before writes had sequence numbers
+            // are numbered in log order, after everything already on disk.
+            let seq = if entry.seq == 0 {
+                *last_seq + 1
+            } else {
+                entry.seq
+            };
+            *last_seq = (*last_seq).max(seq);
+
+            let res = memtable.apply(&entry.key, &entry.value, seq);
+
+            // The memtable may grow past its limit while replaying, it gets
+            // flushed on the next write like it would have before the restart.
+            match res {
+                Ok(()) | Err(ShortDBErrors::FlushNeededFromMemTable) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Drains the immutable memtables into L0 SSTs, oldest first, whenever a
+    /// memtable gets frozen or `flush` asks for it.
+    fn start_flush_thread(
+        requests: Receiver<FlushRequest>,
+        dir: PathBuf,
+        immutables: ImmutableQueue,
+        levels: Arc<Levels>,
+        compaction_signal: Sender<()>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            // Exits once the database is closed, and with it the sender dropped
+            while let Ok(waiter) = requests.recv() {
+                let result = Self::flush_immutables(&dir, &immutables, &levels, &compaction_signal);
+                if let Err(e) = &result {
+                    // Keep it queued, the next request retries the flush
+                    eprintln!("Error flushing memtable to SST: {:?}", e);
+                }
+                if let Some(done) = waiter {
+                    let _ = done.send(result);
+                }
+            }
+        })
+    }
+
+    fn flush_immutables(
+        dir: &Path,
+        immutables: &ImmutableQueue,
+        levels: &Levels,
+        compaction_signal: &Sender<()>,
+    ) -> Result<()> {
+        loop {
+            let Some(oldest) = immutables.read().front().cloned() else {
+                return Ok(());
+            };
+            let entries = oldest
+                .memtable
+                .memtable
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()));
+            levels.flush(entries, oldest.next_wal)?;
+
+            // Only drop the memtable from the read path once the SST can
+            // serve its entries
+            immutables.write().pop_front();
+            // Recovery skips them even if they can't be removed now
+            if let Err(e) = WAL::remove_segments_before(dir, oldest.next_wal) {
+                eprintln!("Error removing flushed WAL segments: {:?}", e);
+            }
+            let _ = compaction_signal.send(());
+        }
+    }
+
+    /// Fsyncs the WAL every `interval`, for `SyncMode::Periodic`, until
+    /// the sender of `stop` is dropped.
+    fn start_sync_thread(
+        interval: Duration,
+        stop: Receiver<()>,
+        wal: Weak<Mutex<WAL>>,
+        wal_sync: Arc<GroupSync>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+                let Some(wal) = wal.upgrade() else {
+                    break;
+                };
+                let appended = wal.lock().appended();
+                if let Err(e) = wal_sync.sync_through(appended, &wal) {
+                    eprintln!("Error syncing WAL: {:?}", e);
+                }
+            }
+        })
+    }
+
+    /// Compacts the SSTs in the background until every level is back within
+    /// its limits, whenever a flush added a file to L0.
+    fn start_compaction_thread(requests: Receiver<()>, levels: Arc<Levels>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            // Exits once the flush thread, and with it the sender, is gone
+            while requests.recv().is_ok() {
+                loop {
+                    match levels.maybe_compact() {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) => {
+                            eprintln!("Error compacting SSTs: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Flushes the memtable to an SST, returning once every write made
+    /// before the call is in one.
+    pub fn flush(&self) -> Result<()> {
+        let mut wal = self.wal.lock();
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(ShortDBErrors::Closed);
+        }
+        let flushed = self.request_flush(&mut wal)?;
+        drop(wal);
+        flushed.wait()
+    }
+
+    /// Freezes the memtable and asks the flush thread to flush everything
+    /// frozen so far.
+    fn request_flush(&self, wal: &mut WAL) -> Result<FlushHandle> {
+        if !self.memtable.read().memtable.is_empty() {
+            self.flush_memtable(wal)?;
+        }
+        let (done, flushed) = FlushHandle::new();
+        match &*self.background.lock() {
+            Some(background) => {
+                let _ = background.flush_signal.send(Some(done));
+            }
+            None => return Err(ShortDBErrors::Closed),
+        }
+        Ok(flushed)
+    }
+
+    /// Closes the database: stops taking writes, flushes the memtables,
+    /// fsyncs the WAL and the data directory and joins the background
+    /// threads. Reads keep working on what was persisted.
+    ///
+    /// Closing again does nothing. Dropping the database closes it too, but
+    /// can only log what failed.
+    pub fn close(&self) -> Result<()> {
+        let mut wal = self.wal.lock();
+        if self.closed.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        // The WAL still has every write should the flush fail
+        let synced = wal.sync();
+        let flushed = self.request_flush(&mut wal);
+        // Writes fail from now on; the periodic sync thread takes the WAL
+        // lock, so it must be free before the thread can be joined
+        drop(wal);
+        let flushed = flushed.and_then(|flushed| flushed.wait());
+        let background = self.background.lock().take();
+        if let Some(background) = background {
+            background.stop();
+        }
+        let dir_synced = File::open(&self.data_dir).and_then(|dir| dir.sync_all());
+
+        synced?;
+        flushed?;
+        dir_synced?;
+        Ok(())
+    }
+
+    /// Compacts every SST file overlapping `[start, end]`, dropping
+    /// overwritten values and tombstones that have nothing left to hide.
+    ///
+    /// Only data already flushed to SSTs is compacted, and versions a live
+    /// snapshot still reads are kept.
+    pub fn compact_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.levels.compact_range(start, end)?;
+        Ok(())
+    }
+
+    /// How much the compaction strategy rewrote the flushed data so far.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.levels.stats()
+    }
+
+    /// How often the SST filters spared a lookup, let an absent key
+    /// through, and how much memory they take.
+    pub fn filter_stats(&self) -> FilterStats {
+        self.levels.filter_stats()
+    }
+
+    /// Takes a snapshot of the database: reads through it keep seeing the
+    /// current state while later writes go on. Compaction keeps the versions
+    /// it reads until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        // Pinned before any later write, and with it a compaction dropping
+        // the versions the snapshot reads, can happen
+        let _writers = self.wal.lock();
+        self.snapshots.pin(self.seq.load(Ordering::Acquire))
+    }
+
+    /// Begins an optimistic transaction reading the current state of the
+    /// database.
+    pub fn begin_transaction(&self) -> Transaction<'_> {
+        Transaction::optimistic(self)
+    }
+
+    /// Begins a pessimistic transaction, which locks the keys it reads for
+    /// update or writes until it ends.
+    pub fn begin_pessimistic_transaction(&self) -> Transaction<'_> {
+        Transaction::pessimistic(self)
+    }
+
+    pub(crate) fn locks(&self) -> &LockManager {
+        &self.locks
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        // First check in Memtable
-        // if let Some(value) = self.memtable.get(key) {
-        //     return Ok(Some(value));
-        // }
-        //
-
-        match self.memtable.get(key) {
-            Ok(None) => println!("data deleted"),
-            Ok(Some(v)) => {
-                return Ok(Some(v));
+        self.get_at_seq(key, self.seq.load(Ordering::Acquire))
+    }
+
+    /// Looks `key` up as it was when `snapshot` was taken.
+    pub fn get_at(&self, key: &[u8], snapshot: &Snapshot) -> Result<Option<Bytes>> {
+        self.get_at_seq(key, snapshot.seq())
+    }
+
+    fn get_at_seq(&self, key: &[u8], seq: u64) -> Result<Option<Bytes>> {
+        // Check the active memtable, then the immutable ones from newest to
+        // oldest; a tombstone (Ok(None)) in any of them hides older values.
+        match self.memtable.read().get(key, seq) {
+            Err(ShortDBErrors::KeyNotFound) => {}
+            found => return found,
+        }
+        for immutable in self.immutables.read().iter().rev() {
+            match immutable.memtable.get(key, seq) {
+                Err(ShortDBErrors::KeyNotFound) => {}
+                found => return found,
             }
-            Err(ShortDBErrors::KeyNotFound) => println!("not found in mem"),
-            Err(e) => println!("something problematic happend {}", e),
         }
-        dbg!("checking in sst");
-        // If not found in Memtable, check SST
-        if let Some(value) = self.sst.get(key) {
-            return Ok(Some(value));
+
+        // If not found in any memtable, check the SSTs
+        if let Some((_, value)) = self.levels.get(key, seq)? {
+            return Ok(value.as_put().cloned());
         }
 
         Err(ShortDBErrors::KeyNotFound) // Return None if not found
     }
 
-    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        // Create a new WALEntry
-        let entry = WALEntry {
-            key: Bytes::copy_from_slice(key),
-            value: Bytes::copy_from_slice(value),
-        };
+    /// Iterates over the live keys in `range` and their newest values, in
+    /// ascending key order; `.rev()` walks the range in descending order.
+    ///
+    /// SST blocks are read as the iteration reaches them: one that turns
+    /// out damaged is yielded as a `Corruption` error, ending the iteration.
+    pub fn scan<K, R>(&self, range: R) -> Result<DBIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.scan_range(to_key_range(range), None, self.seq.load(Ordering::Acquire))
+    }
+
+    /// Iterates over the keys in `range` as they were when `snapshot` was
+    /// taken.
+    pub fn scan_at<K, R>(&self, range: R, snapshot: &Snapshot) -> Result<DBIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.scan_range(to_key_range(range), None, snapshot.seq())
+    }
+
+    /// Iterates over every key starting with `prefix`, in key order.
+    ///
+    /// When `prefix` is a complete prefix of the `prefix_extractor` the
+    /// database was opened with, SST files that hold no key with it are
+    /// skipped without being read.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<DBIterator> {
+        self.scan_range(
+            prefix_range(prefix),
+            Some(prefix),
+            self.seq.load(Ordering::Acquire),
+        )
+    }
+
+    fn scan_range(&self, range: KeyRange, prefix: Option<&[u8]>, seq: u64) -> Result<DBIterator> {
+        // Newest first: memtables before SSTs, so a flush racing with the
+        // scan at worst shows a memtable twice, never loses it.
+        let mut sources: Vec<Entries> = vec![Box::new(self.memtable.read().scan(&range).map(Ok))];
+        for immutable in self.immutables.read().iter().rev() {
+            sources.push(Box::new(immutable.memtable.scan(&range).map(Ok)));
+        }
+        for table in self.levels.scan(&range, prefix)? {
+            sources.push(Box::new(table));
+        }
+        Ok(DBIterator::new(sources, seq))
+    }
+
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.set_with_sync(key, value, self.sync_mode)
+    }
+
+    /// Sets `key` like `set`, fsyncing the WAL as `sync` says instead of as
+    /// the database was opened with.
+    pub fn set_with_sync(&self, key: &[u8], value: &[u8], sync: SyncMode) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write_with_sync(&batch, sync)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(&batch)
+    }
+
+    /// Applies every write of `batch` atomically: they are logged as a
+    /// single WAL record, and readers see either all of them or none.
+    ///
+    /// Keys locked by a pessimistic transaction are waited for until it
+    /// ends; the write fails with `LockTimeout` or `Deadlock` like taking
+    /// the lock in a transaction would.
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.write_with_sync(batch, self.sync_mode)
+    }
+
+    /// Applies `batch` like `write`, fsyncing the WAL as `sync` says.
+    pub fn write_with_sync(&self, batch: &WriteBatch, sync: SyncMode) -> Result<()> {
+        let row_locks = self.lock_rows(batch)?;
+        let record = self.write_locked(&mut self.wal.lock(), batch, sync)?;
+        drop(row_locks);
+        self.wait_synced(record, sync)
+    }
+
+    /// Applies the batch of a pessimistic transaction, which already holds
+    /// the locks on its keys.
+    pub(crate) fn write_holding_locks(&self, batch: &WriteBatch) -> Result<()> {
+        let record = self.write_locked(&mut self.wal.lock(), batch, self.sync_mode)?;
+        self.wait_synced(record, self.sync_mode)
+    }
+
+    /// Locks the keys `batch` writes for the time it takes to apply it,
+    /// waiting for the pessimistic transactions holding any of them to end.
+    /// Taken before the WAL lock, which the holders need to commit.
+    fn lock_rows(&self, batch: &WriteBatch) -> Result<RowLocks<'_>> {
+        self.locks
+            .lock_all(batch.entries().iter().map(|(key, _)| key.as_ref()))
+    }
+
+    /// Applies `batch` unless one of `keys` got a version newer than `seq`,
+    /// failing with `TransactionConflict` then. The keys are checked under
+    /// the write lock, so no other write slips in before the batch; the
+    /// keys of the batch are locked like `write` does.
+    pub(crate) fn write_if_unchanged<'k, I>(
+        &self,
+        batch: &WriteBatch,
+        keys: I,
+        seq: u64,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = &'k [u8]>,
+    {
+        let row_locks = self.lock_rows(batch)?;
+        let mut wal = self.wal.lock();
+        for key in keys {
+            if self.newest_seq(key)?.is_some_and(|newest| newest > seq) {
+                return Err(ShortDBErrors::TransactionConflict);
+            }
+        }
+        let record = self.write_locked(&mut wal, batch, self.sync_mode)?;
+        drop(wal);
+        drop(row_locks);
+        self.wait_synced(record, self.sync_mode)
+    }
+
+    /// Sequence number of the newest version of `key`, tombstones included.
+    fn newest_seq(&self, key: &[u8]) -> Result<Option<u64>> {
+        if let Some((seq, _)) = self.memtable.read().newest(key, MAX_SEQ) {
+            return Ok(Some(seq));
+        }
+        for immutable in self.immutables.read().iter().rev() {
+            if let Some((seq, _)) = immutable.memtable.newest(key, MAX_SEQ) {
+                return Ok(Some(seq));
+            }
+        }
+        Ok(self.levels.get(key, MAX_SEQ)?.map(|(seq, _)| seq))
+    }
+
+    /// Writes `batch` under the WAL lock, returning the number of WAL
+    /// records written once its own is; fsyncs that happen outside the lock
+    /// are left to `wait_synced`.
+    fn write_locked(&self, wal: &mut WAL, batch: &WriteBatch, sync: SyncMode) -> Result<u64> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(ShortDBErrors::Closed);
+        }
+        if batch.is_empty() {
+            return Ok(wal.appended());
+        }
+        let last_seq = self.seq.load(Ordering::Acquire);
+        let entries: Vec<WALEntry> = batch
+            .entries()
+            .iter()
+            .zip(last_seq + 1..)
+            .map(|((key, value), seq)| WALEntry {
+                key: key.clone(),
+                value: value.clone(),
+                seq,
+            })
+            .collect();
 
         // Write to the WAL
-        self.wal.write(&entry)?;
+        wal.write(&entries)?;
+        let record = wal.appended();
+        if sync == SyncMode::Always {
+            wal.sync()?;
+        }
 
-        // Insert into Memtable
-        self.memtable.set(key, value)?;
+        // Insert into Memtable; a full memtable is only flushed once the
+        // whole batch is in, so the batch never straddles two WALs.
+        let mut flush_needed = false;
+        {
+            let mut memtable = self.memtable.write();
+            for entry in &entries {
+                match memtable.apply(&entry.key, &entry.value, entry.seq) {
+                    Ok(()) => {}
+                    Err(ShortDBErrors::FlushNeededFromMemTable) => flush_needed = true,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
 
-        // Check if we need to flush Memtable to SST
-        if let Err(err) = self.memtable.set(key, value) {
-            match err {
-                ShortDBErrors::FlushNeededFromMemTable => self.flush_memtable()?,
-                _ => println!("{}", err),
+        // Reads only see versions up to `seq`, publishing the batch at once
+        self.seq
+            .store(last_seq + entries.len() as u64, Ordering::Release);
+        if flush_needed {
+            // The batch is written whether or not this works; the memtable
+            // stays full then, so the next write tries again
+            if let Err(e) = self.flush_memtable(wal) {
+                eprintln!("Error freezing the full memtable: {:?}", e);
             }
         }
 
+        Ok(record)
+    }
+
+    /// Waits for the first `record` WAL records to be on disk, if `sync`
+    /// asks for it.
+    fn wait_synced(&self, record: u64, sync: SyncMode) -> Result<()> {
+        let due = match sync {
+            SyncMode::GroupCommit => true,
+            SyncMode::Periodic(interval) => self.wal_sync.is_due(interval),
+            SyncMode::Always | SyncMode::Disabled => false,
+        };
+        if due {
+            self.wal_sync.sync_through(record, &self.wal)?;
+        }
+        Ok(())
+    }
+
+    fn flush_memtable(&self, wal: &mut WAL) -> Result<()> {
+        // Freeze the full memtable together with its WAL and hand it to the
+        // flush thread, writes continue on a fresh memtable right away.
+        let next_wal = wal.rotate()?;
+        // Queued while the active memtable is still locked, so readers find
+        // its entries in either place
+        let mut active = self.memtable.write();
+        let memtable = active.freeze();
+        self.immutables
+            .write()
+            .push_back(Arc::new(ImmutableMemtable { memtable, next_wal }));
+        drop(active);
+        if let Some(background) = &*self.background.lock() {
+            let _ = background.flush_signal.send(None);
+        }
+
         Ok(())
     }
+}
+
+impl Drop for ShorterDB {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            eprintln!("Error closing the database: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::prefix::{DelimitedPrefix, FixedPrefix};
+    use crate::kv::testing::{crash_copy, test_dir};
+    use crate::kv::wal::{DropReason, RecoveryMode};
+
+    #[test]
+    fn close_in_every_sync_mode() {
+        let modes = [
+            ("always", SyncMode::Always),
+            ("periodic", SyncMode::Periodic(Duration::from_millis(1))),
+            ("group-commit", SyncMode::GroupCommit),
+            ("disabled", SyncMode::Disabled),
+        ];
+        for (name, sync_mode) in modes {
+            let dir = test_dir(&format!("close-{}", name));
+            let options = Options {
+                sync_mode,
+                ..Default::default()
+            };
+            let db = ShorterDB::with_options(&dir, options.clone()).unwrap();
+            for i in 0..600 {
+                db.set(format!("key-{:04}", i).as_bytes(), b"value")
+                    .unwrap();
+            }
+            db.close().unwrap();
+            assert!(matches!(
+                db.set(b"key", b"value"),
+                Err(ShortDBErrors::Closed)
+            ));
+            assert_eq!(db.get(b"key-0042").unwrap(), Some(Bytes::from("value")));
+            drop(db);
+
+            // Dropping an open database closes it as well
+            let db = ShorterDB::with_options(&dir, options).unwrap();
+            assert_eq!(db.get(b"key-0599").unwrap(), Some(Bytes::from("value")));
+        }
+    }
+
+    #[test]
+    fn periodic_sync_needs_an_interval() {
+        let options = Options {
+            sync_mode: SyncMode::Periodic(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(ShorterDB::with_options(test_dir("periodic-zero"), options).is_err());
+    }
+
+    fn collect<I: Iterator<Item = Result<(Bytes, Bytes)>>>(iter: I) -> Vec<(Bytes, Bytes)> {
+        iter.collect::<Result<_>>().unwrap()
+    }
+
+    fn pairs(keys: &[(&str, &str)]) -> Vec<(Bytes, Bytes)> {
+        keys.iter()
+            .map(|(key, value)| (Bytes::from(key.to_string()), Bytes::from(value.to_string())))
+            .collect()
+    }
 
-    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        // Create a tombstone entry
-        let tombstone_entry = WALEntry {
-            key: Bytes::copy_from_slice(key),
-            value: Bytes::copy_from_slice(b"tombstone"),
+    #[test]
+    fn scan_merges_memtables_and_sst_blocks() {
+        let options = Options {
+            block_size: 64,
+            ..Default::default()
         };
+        let db = ShorterDB::with_options(test_dir("scan-merge"), options).unwrap();
+        for i in 0..100 {
+            db.set(format!("key-{:03}", i).as_bytes(), b"old").unwrap();
+        }
+        db.flush().unwrap();
+        for i in (0..100).step_by(10) {
+            db.set(format!("key-{:03}", i).as_bytes(), b"new").unwrap();
+        }
+        db.delete(b"key-011").unwrap();
+        db.flush().unwrap();
+        db.set(b"key-012", b"newest").unwrap();
+        db.delete(b"key-013").unwrap();
 
-        // Write tombstone to WAL
-        self.wal.write(&tombstone_entry)?;
+        let scanned = collect(db.scan(&b"key-009"[..]..=&b"key-015"[..]).unwrap());
+        let expected = pairs(&[
+            ("key-009", "old"),
+            ("key-010", "new"),
+            ("key-012", "newest"),
+            ("key-014", "old"),
+            ("key-015", "old"),
+        ]);
+        assert_eq!(scanned, expected);
 
-        // Delete from Memtable
-        self.memtable.delete(key)?;
+        let mut reversed = collect(db.scan(&b"key-009"[..]..=&b"key-015"[..]).unwrap().rev());
+        reversed.reverse();
+        assert_eq!(reversed, expected);
 
-        // Check if we need to flush Memtable to SST
-        if let Err(err) = self.memtable.delete(key) {
-            match err {
-                ShortDBErrors::FlushNeededFromMemTable => self.flush_memtable()?,
-                _ => println!("{:?}", err),
+        // Both ends meet in the middle without repeating a key
+        let mut iter = db.scan::<&[u8], _>(..).unwrap();
+        let mut keys = Vec::new();
+        while let Some(front) = iter.next() {
+            keys.push(front.unwrap().0);
+            if let Some(back) = iter.next_back() {
+                keys.push(back.unwrap().0);
             }
         }
+        keys.sort();
+        assert_eq!(keys.len(), 98);
+        keys.dedup();
+        assert_eq!(keys.len(), 98);
+    }
 
-        Ok(())
+    #[test]
+    fn scan_at_snapshot_ignores_later_writes() {
+        let db = ShorterDB::new(test_dir("scan-snapshot")).unwrap();
+        db.set(b"a", b"1").unwrap();
+        db.set(b"b", b"1").unwrap();
+        let snapshot = db.snapshot();
+        db.set(b"a", b"2").unwrap();
+        db.delete(b"b").unwrap();
+        db.set(b"c", b"2").unwrap();
+        db.flush().unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+
+        let scanned = collect(db.scan_at::<&[u8], _>(.., &snapshot).unwrap());
+        assert_eq!(scanned, pairs(&[("a", "1"), ("b", "1")]));
+        let scanned = collect(db.scan::<&[u8], _>(..).unwrap());
+        assert_eq!(scanned, pairs(&[("a", "2"), ("c", "2")]));
     }
 
-    fn flush_memtable(&mut self) -> Result<()> {
-        // Flush entries from Memtable to SST
-        // for entry in self.memtable.memtable.iter() {
-        //     self.sst.set(entry.key().as_ref(), entry.value().as_ref())?;
-        // }
+    #[test]
+    fn reopen_replays_unflushed_writes() {
+        let dir = test_dir("reopen-replay");
+        let db = ShorterDB::new(&dir).unwrap();
+        db.set(b"kept", b"1").unwrap();
+        db.set(b"overwritten", b"1").unwrap();
+        db.set(b"overwritten", b"2").unwrap();
+        db.set(b"deleted", b"1").unwrap();
+        db.delete(b"deleted").unwrap();
 
-        // println!("Memtable: Flushing {} entries to SST", self.memtable.len());
-        // for entry in self.memtable.memtable.iter() {
-        //     self.sst.set(entry.key().as_ref(), entry.value().as_ref());
-        // }
-        self.memtable.clear();
-        // self.sst.flush();
-        // self.sst.queue.push_back(self.memtable.memtable);
+        // Killed before anything reached an SST
+        let crashed = crash_copy(&dir);
+        let reopened = ShorterDB::new(&crashed).unwrap();
+        assert!(reopened.recovery_report().is_clean());
+        assert_eq!(reopened.recovery_report().records_replayed, 5);
+        assert_eq!(reopened.get(b"kept").unwrap(), Some(Bytes::from("1")));
+        assert_eq!(
+            reopened.get(b"overwritten").unwrap(),
+            Some(Bytes::from("2"))
+        );
+        assert_eq!(reopened.get(b"deleted").unwrap(), None);
 
-        Ok(())
+        // New writes are numbered after the replayed ones
+        reopened.set(b"overwritten", b"3").unwrap();
+        drop(reopened);
+        let reopened = ShorterDB::new(&crashed).unwrap();
+        assert_eq!(
+            reopened.get(b"overwritten").unwrap(),
+            Some(Bytes::from("3"))
+        );
+    }
+
+    /// An entry of a WAL written before deletes were typed.
+    fn legacy_wal_entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        entry.extend_from_slice(value);
+        entry
+    }
+
+    #[test]
+    fn tombstones_hide_flushed_values_across_reopen() {
+        let dir = test_dir("tombstones-reopen");
+        let db = ShorterDB::new(&dir).unwrap();
+        db.set(b"a", b"1").unwrap();
+        db.set(b"b", b"1").unwrap();
+        db.flush().unwrap();
+        db.delete(b"a").unwrap();
+        // Looks just like the sentinel older versions stored for a delete
+        db.set(b"b", b"tombstone").unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let db = ShorterDB::new(&dir).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), None);
+        assert_eq!(db.get(b"b").unwrap(), Some(Bytes::from("tombstone")));
+        let scanned = collect(db.scan::<&[u8], _>(..).unwrap());
+        assert_eq!(scanned, pairs(&[("b", "tombstone")]));
+    }
+
+    #[test]
+    fn legacy_wal_sentinel_is_read_as_delete() {
+        let dir = test_dir("legacy-wal-tombstone");
+        let mut log = legacy_wal_entry(b"a", b"1");
+        log.extend(legacy_wal_entry(b"b", b"1"));
+        log.extend(legacy_wal_entry(b"a", b"tombstone"));
+        fs::write(dir.join("wal.log"), log).unwrap();
+
+        let db = ShorterDB::new(&dir).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), None);
+        assert_eq!(db.get(b"b").unwrap(), Some(Bytes::from("1")));
+    }
+
+    #[test]
+    fn legacy_wal_becomes_a_segment() {
+        let dir = test_dir("legacy-wal-migration");
+        fs::write(dir.join("wal.log"), legacy_wal_entry(b"a", b"1")).unwrap();
+
+        let db = ShorterDB::new(&dir).unwrap();
+        assert!(!dir.join("wal.log").exists());
+        db.set(b"b", b"2").unwrap();
+        let crashed = crash_copy(&dir);
+
+        let db = ShorterDB::new(&crashed).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), Some(Bytes::from("1")));
+        assert_eq!(db.get(b"b").unwrap(), Some(Bytes::from("2")));
+    }
+
+    #[test]
+    fn wal_segments_rotate_and_go_once_flushed() {
+        let dir = test_dir("wal-segments");
+        let options = Options {
+            max_wal_segment_size: 256,
+            ..Default::default()
+        };
+        let db = ShorterDB::with_options(&dir, options.clone()).unwrap();
+        for i in 0..50 {
+            db.set(format!("key-{:02}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        assert!(WAL::segments(&dir).unwrap().len() > 2);
+
+        // Every segment is replayed after a crash
+        let crashed = crash_copy(&dir);
+        let reopened = ShorterDB::with_options(&crashed, options).unwrap();
+        assert_eq!(reopened.recovery_report().records_replayed, 50);
+        assert_eq!(reopened.get(b"key-49").unwrap(), Some(Bytes::from("value")));
+
+        // Only the segment being written outlives a flush
+        db.flush().unwrap();
+        assert_eq!(WAL::segments(&dir).unwrap().len(), 1);
+        drop(db);
+        let db = ShorterDB::new(&dir).unwrap();
+        assert_eq!(db.recovery_report().records_replayed, 0);
+        assert_eq!(db.get(b"key-00").unwrap(), Some(Bytes::from("value")));
+    }
+
+    /// A database whose WAL holds the records setting `a`, `b` and `c`,
+    /// after `damage` went over the segment with the length of a record.
+    fn damaged_wal(name: &str, damage: impl Fn(&mut Vec<u8>, usize)) -> PathBuf {
+        let dir = test_dir(name);
+        let db = ShorterDB::new(&dir).unwrap();
+        for key in ["a", "b", "c"] {
+            db.set(key.as_bytes(), b"1").unwrap();
+        }
+        let crashed = crash_copy(&dir);
+        let (_, segment) = WAL::segments(&crashed).unwrap().pop().unwrap();
+        let mut data = fs::read(&segment).unwrap();
+        let header_len = 8;
+        let record_len = (data.len() - header_len) / 3;
+        damage(&mut data, record_len);
+        fs::write(&segment, data).unwrap();
+        crashed
+    }
+
+    fn open_with(dir: &Path, recovery_mode: RecoveryMode) -> Result<ShorterDB> {
+        let options = Options {
+            recovery_mode,
+            ..Default::default()
+        };
+        ShorterDB::with_options(dir, options)
+    }
+
+    fn present(db: &ShorterDB) -> Vec<&'static str> {
+        ["a", "b", "c"]
+            .into_iter()
+            .filter(|key| db.get(key.as_bytes()).is_ok())
+            .collect()
+    }
+
+    #[test]
+    fn recovery_modes_on_a_torn_tail() {
+        let torn = |name| damaged_wal(name, |data, _| data.truncate(data.len() - 3));
+        let dir = torn("torn-absolute");
+        assert!(open_with(&dir, RecoveryMode::AbsoluteConsistency).is_err());
+
+        for (name, mode) in [
+            ("torn-tolerate", RecoveryMode::TolerateCorruptedTail),
+            ("torn-point-in-time", RecoveryMode::PointInTime),
+            ("torn-skip", RecoveryMode::SkipAnyCorruptedRecord),
+        ] {
+            let dir = torn(name);
+            let db = open_with(&dir, mode).unwrap();
+            assert_eq!(present(&db), ["a", "b"]);
+            let reasons: Vec<_> = db
+                .recovery_report()
+                .dropped
+                .iter()
+                .map(|range| range.reason)
+                .collect();
+            assert_eq!(reasons, [DropReason::TornTail]);
+        }
+    }
+
+    #[test]
+    fn recovery_modes_on_corruption_mid_log() {
+        let corrupted = |name| {
+            damaged_wal(name, |data, record_len| {
+                data[8 + record_len + record_len / 2] ^= 0xff;
+            })
+        };
+        for (name, mode) in [
+            ("corrupted-absolute", RecoveryMode::AbsoluteConsistency),
+            ("corrupted-tolerate", RecoveryMode::TolerateCorruptedTail),
+        ] {
+            assert!(open_with(&corrupted(name), mode).is_err());
+        }
+
+        let dir = corrupted("corrupted-point-in-time");
+        let db = open_with(&dir, RecoveryMode::PointInTime).unwrap();
+        assert_eq!(present(&db), ["a"]);
+        let reasons: Vec<_> = db
+            .recovery_report()
+            .dropped
+            .iter()
+            .map(|range| range.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            [DropReason::Corrupted, DropReason::AfterCorruption]
+        );
+
+        let dir = corrupted("corrupted-skip");
+        let db = open_with(&dir, RecoveryMode::SkipAnyCorruptedRecord).unwrap();
+        assert_eq!(present(&db), ["a", "c"]);
+        assert_eq!(db.recovery_report().records_replayed, 2);
+
+        // What was recovered is persisted, and the damaged log retired
+        drop(db);
+        let db = open_with(&dir, RecoveryMode::AbsoluteConsistency).unwrap();
+        assert!(db.recovery_report().is_clean());
+        assert_eq!(present(&db), ["a", "c"]);
+    }
+
+    #[test]
+    fn damage_in_a_sealed_segment_is_corruption() {
+        let dir = test_dir("sealed-segment-damaged");
+        let options = Options {
+            max_wal_segment_size: 1,
+            ..Default::default()
+        };
+        let db = ShorterDB::with_options(&dir, options).unwrap();
+        for key in ["a", "b", "c"] {
+            db.set(key.as_bytes(), b"1").unwrap();
+        }
+        // Each write went to a segment of its own; tear the first one
+        let crashed = crash_copy(&dir);
+        let (_, segment) = WAL::segments(&crashed).unwrap().remove(0);
+        let data = fs::read(&segment).unwrap();
+        fs::write(&segment, &data[..data.len() - 3]).unwrap();
+
+        assert!(open_with(&crashed, RecoveryMode::TolerateCorruptedTail).is_err());
+        let db = open_with(&crashed, RecoveryMode::SkipAnyCorruptedRecord).unwrap();
+        assert_eq!(present(&db), ["b", "c"]);
+        let reasons: Vec<_> = db
+            .recovery_report()
+            .dropped
+            .iter()
+            .map(|range| range.reason)
+            .collect();
+        assert_eq!(reasons, [DropReason::Corrupted]);
+    }
+
+    /// An entry of a `data.sst` written before the MANIFEST existed, a
+    /// bincode `LegacyKeyValuePair`.
+    fn legacy_sst_entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        entry.extend_from_slice(value);
+        entry.extend_from_slice(&0u64.to_le_bytes()); // timestamp
+        entry
+    }
+
+    #[test]
+    fn legacy_data_sst_joins_the_manifest() {
+        let dir = test_dir("legacy-sst-migration");
+        let mut table = legacy_sst_entry(b"a", b"1");
+        table.extend(legacy_sst_entry(b"b", b"tombstone"));
+        table.extend(legacy_sst_entry(b"c", b"1"));
+        fs::write(dir.join("data.sst"), table).unwrap();
+        fs::write(dir.join("wal.log"), legacy_wal_entry(b"c", b"2")).unwrap();
+
+        let db = ShorterDB::new(&dir).unwrap();
+        assert!(!dir.join("data.sst").exists());
+        // The log is newer than the table
+        assert_eq!(db.get(b"a").unwrap(), Some(Bytes::from("1")));
+        assert_eq!(db.get(b"b").unwrap(), None);
+        assert_eq!(db.get(b"c").unwrap(), Some(Bytes::from("2")));
+
+        db.compact_range(b"a", b"z").unwrap();
+        drop(db);
+        let db = ShorterDB::new(&dir).unwrap();
+        let scanned = collect(db.scan::<&[u8], _>(..).unwrap());
+        assert_eq!(scanned, pairs(&[("a", "1"), ("c", "2")]));
+    }
+
+    #[test]
+    fn scan_prefix_reads_memtables_and_tables() {
+        let dir = test_dir("scan-prefix");
+        let options = Options {
+            prefix_extractor: Some(Arc::new(DelimitedPrefix {
+                delimiter: b':',
+                count: 2,
+            })),
+            ..Default::default()
+        };
+        let db = ShorterDB::with_options(&dir, options).unwrap();
+        for id in 0..10 {
+            for field in ["email", "name"] {
+                db.set(format!("user:{}:{}", id, field).as_bytes(), b"old")
+                    .unwrap();
+            }
+        }
+        db.flush().unwrap();
+        db.set(b"user:3:name", b"new").unwrap();
+        db.flush().unwrap();
+        db.delete(b"user:3:email").unwrap();
+        db.set(b"user:3:phone", b"new").unwrap();
+        db.set(b"user:30:name", b"new").unwrap();
+
+        let user = pairs(&[("user:3:name", "new"), ("user:3:phone", "new")]);
+        assert_eq!(collect(db.scan_prefix(b"user:3:").unwrap()), user);
+        assert_eq!(collect(db.scan_prefix(b"user:").unwrap()).len(), 21);
+        assert!(collect(db.scan_prefix(b"user:42:").unwrap()).is_empty());
+        drop(db);
+
+        // Tables with the prefix filter of another extractor are read whole
+        let options = Options {
+            prefix_extractor: Some(Arc::new(FixedPrefix(7))),
+            ..Default::default()
+        };
+        let db = ShorterDB::with_options(&dir, options).unwrap();
+        assert_eq!(collect(db.scan_prefix(b"user:3:").unwrap()), user);
+    }
+
+    #[test]
+    fn torn_batch_is_dropped_whole() {
+        let dir = test_dir("torn-batch");
+        let db = ShorterDB::new(&dir).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"1");
+        batch.put(b"b", b"1");
+        db.write(&batch).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.delete(b"a");
+        batch.put(b"b", b"2");
+        batch.put(b"c", b"2");
+        db.write(&batch).unwrap();
+
+        // The process died while the second batch was being logged
+        let crashed = crash_copy(&dir);
+        let (_, segment) = WAL::segments(&crashed).unwrap().pop().unwrap();
+        let data = fs::read(&segment).unwrap();
+        fs::write(&segment, &data[..data.len() - 3]).unwrap();
+
+        let db = ShorterDB::new(&crashed).unwrap();
+        assert_eq!(db.recovery_report().records_replayed, 1);
+        assert_eq!(db.get(b"a").unwrap(), Some(Bytes::from("1")));
+        assert_eq!(db.get(b"b").unwrap(), Some(Bytes::from("1")));
+        assert!(matches!(db.get(b"c"), Err(ShortDBErrors::KeyNotFound)));
+    }
+
+    #[test]
+    fn readers_never_see_part_of_a_batch() {
+        let options = Options {
+            sync_mode: SyncMode::Disabled,
+            ..Default::default()
+        };
+        let db = ShorterDB::with_options(test_dir("batch-visibility"), options).unwrap();
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..500 {
+                    let value = i.to_string();
+                    let mut batch = WriteBatch::new();
+                    for key in ["a", "b", "c"] {
+                        batch.put(key.as_bytes(), value.as_bytes());
+                    }
+                    db.write(&batch).unwrap();
+                }
+                done.store(true, Ordering::Release);
+            });
+            while !done.load(Ordering::Acquire) {
+                let values: Vec<_> = collect(db.scan::<&[u8], _>(..).unwrap())
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect();
+                assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+                let snapshot = db.snapshot();
+                let a = db.get_at(b"a", &snapshot).ok();
+                assert_eq!(db.get_at(b"c", &snapshot).ok(), a);
+            }
+        });
     }
 }
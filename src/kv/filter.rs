@@ -0,0 +1,340 @@
+use bloomfilter::Bloom;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Builds the filters SSTs keep over their keys, and their key prefixes,
+/// so lookups can skip the tables that don't hold them.
+pub trait FilterPolicy: Send + Sync {
+    /// Identifies how the policy encodes its filters. SSTs remember the
+    /// name of the policy their filters were built with, and the filters
+    /// are only used while the database runs with the same one.
+    fn name(&self) -> String;
+
+    /// A filter holding every item of `items`, which are distinct.
+    /// `bits_per_key` is the memory a bloom filter would spend per item;
+    /// other policies aim for the false positive rate that bloom filter
+    /// would have.
+    fn build(&self, items: &[&[u8]], bits_per_key: usize) -> Vec<u8>;
+
+    /// Decodes a filter built by `build`, `None` if it is malformed.
+    fn open(&self, filter: &[u8]) -> Option<Box<dyn KeyFilter>>;
+}
+
+/// A filter as read back from a table.
+pub trait KeyFilter: Send + Sync {
+    /// Whether the filter may hold `item`; never `false` for an item it
+    /// was built with.
+    fn may_contain(&self, item: &[u8]) -> bool;
+
+    /// Bytes of memory the filter takes.
+    fn memory_usage(&self) -> usize;
+}
+
+/// Standard bloom filters, the `bloomfilter` crate's serialized with
+/// bincode. At 10 bits per key about 1% of absent keys get through.
+#[derive(Default)]
+pub struct BloomFilterPolicy;
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> String {
+        "bloom".to_string()
+    }
+
+    fn build(&self, items: &[&[u8]], bits_per_key: usize) -> Vec<u8> {
+        let count = items.len().max(1);
+        let mut bloom = Bloom::new((count * bits_per_key).div_ceil(8), count);
+        for item in items {
+            bloom.set(&item.to_vec());
+        }
+        bincode::serialize(&bloom).expect("bloom filters always serialize")
+    }
+
+    fn open(&self, filter: &[u8]) -> Option<Box<dyn KeyFilter>> {
+        let bloom: Bloom<Vec<u8>> = bincode::deserialize(filter).ok()?;
+        Some(Box::new(bloom))
+    }
+}
+
+impl KeyFilter for Bloom<Vec<u8>> {
+    fn may_contain(&self, item: &[u8]) -> bool {
+        self.check(&item.to_vec())
+    }
+
+    fn memory_usage(&self) -> usize {
+        (self.number_of_bits() / 8) as usize
+    }
+}
+
+/// Columns a ribbon row spans, one bit of its `u64` coefficients each.
+const RIBBON_WIDTH: usize = 64;
+/// Slots of a ribbon filter per item, beyond the width of the last row.
+/// With fewer, the rows of absent items are more often implied by those
+/// stored, and such items always match.
+const RIBBON_OVERHEAD: f64 = 1.10;
+const RIBBON_SEED: u64 = 0x5f0d_1e2b_a7c3_9e41;
+
+/// Homogeneous ribbon filters (Dillinger & Walzer, "Ribbon filter: practically
+/// smaller than Bloom and Xor"): each item is a linear equation over a band
+/// of 64 slots of `result_bits` bits, whose solution is stored. An absent
+/// item only satisfies its equation with probability `2^-result_bits`, so
+/// matching a bloom filter's false positive rate takes about a quarter less
+/// memory. Building is about 4 times slower, lookups are faster; see
+/// `benches/filters.rs`.
+#[derive(Default)]
+pub struct RibbonFilterPolicy;
+
+impl FilterPolicy for RibbonFilterPolicy {
+    fn name(&self) -> String {
+        "ribbon".to_string()
+    }
+
+    fn build(&self, items: &[&[u8]], bits_per_key: usize) -> Vec<u8> {
+        // A bloom filter with its optimal number of probes lets through
+        // 2^-(bits_per_key * ln 2) of the absent items
+        let result_bits =
+            ((bits_per_key as f64 * std::f64::consts::LN_2).ceil() as usize).clamp(1, 32);
+        let starts = ((items.len() as f64 * RIBBON_OVERHEAD).ceil() as usize).max(1);
+        let slots = starts + RIBBON_WIDTH - 1;
+
+        // Gaussian elimination, one row per item: a row is stored in the
+        // slot of its first column, reduced by the rows already there
+        let mut rows = vec![0u64; slots];
+        for item in items {
+            let (mut start, mut coefficients) = ribbon_row(item, starts);
+            loop {
+                let row = rows[start];
+                if row == 0 {
+                    rows[start] = coefficients;
+                    break;
+                }
+                coefficients ^= row;
+                if coefficients == 0 {
+                    break; // implied by the rows stored, every solution satisfies it
+                }
+                let shift = coefficients.trailing_zeros() as usize;
+                start += shift;
+                coefficients >>= shift;
+            }
+        }
+
+        // Back substitution from the last slot; slots without a row are
+        // free and take random bits, so absent items match by chance only
+        let mut solution = vec![0u32; slots];
+        let mask = (u64::MAX >> (64 - result_bits)) as u32;
+        for slot in (0..slots).rev() {
+            let row = rows[slot];
+            solution[slot] = match row {
+                0 => mix(RIBBON_SEED ^ slot as u64) as u32 & mask,
+                _ => (1..RIBBON_WIDTH)
+                    .filter(|column| row >> column & 1 == 1)
+                    .fold(0, |value, column| value ^ solution[slot + column]),
+            };
+        }
+
+        // Stored as one bit vector per result bit
+        let words = slots.div_ceil(64);
+        let mut filter = Vec::with_capacity(9 + result_bits * words * 8);
+        filter.extend_from_slice(&(slots as u64).to_le_bytes());
+        filter.push(result_bits as u8);
+        for bit in 0..result_bits {
+            let mut plane = vec![0u64; words];
+            for (slot, value) in solution.iter().enumerate() {
+                plane[slot / 64] |= u64::from(value >> bit & 1) << (slot % 64);
+            }
+            for word in plane {
+                filter.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        filter
+    }
+
+    fn open(&self, filter: &[u8]) -> Option<Box<dyn KeyFilter>> {
+        let slots = usize::try_from(u64::from_le_bytes(filter.get(..8)?.try_into().ok()?)).ok()?;
+        let result_bits = usize::from(*filter.get(8)?);
+        if slots < RIBBON_WIDTH || !(1..=32).contains(&result_bits) {
+            return None;
+        }
+        let planes = &filter[9..];
+        if planes.len() != result_bits * slots.div_ceil(64) * 8 {
+            return None;
+        }
+        let words = planes
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        Some(Box::new(RibbonFilter {
+            starts: slots - RIBBON_WIDTH + 1,
+            words_per_plane: slots.div_ceil(64),
+            planes: words,
+        }))
+    }
+}
+
+struct RibbonFilter {
+    starts: usize,
+    words_per_plane: usize,
+    planes: Vec<u64>,
+}
+
+impl KeyFilter for RibbonFilter {
+    fn may_contain(&self, item: &[u8]) -> bool {
+        let (start, coefficients) = ribbon_row(item, self.starts);
+        let (word, offset) = (start / 64, start % 64);
+        self.planes.chunks_exact(self.words_per_plane).all(|plane| {
+            // The 64 slots from `start` on, spread over two words
+            let mut band = plane[word] >> offset;
+            if offset > 0 {
+                band |= plane.get(word + 1).map_or(0, |next| next << (64 - offset));
+            }
+            (band & coefficients).count_ones() % 2 == 0
+        })
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.planes.len() * 8
+    }
+}
+
+/// The first slot of the equation of `item` and its coefficients, of which
+/// the lowest bit, for that first slot, is always set.
+fn ribbon_row(item: &[u8], starts: usize) -> (usize, u64) {
+    let hash = hash(item);
+    let start = ((u128::from(hash) * starts as u128) >> 64) as usize;
+    (start, mix(hash ^ RIBBON_SEED) | 1)
+}
+
+/// FNV-1a, finished with `mix`. Filters are persisted, so this must never
+/// change, unlike the hashers of the standard library.
+fn hash(data: &[u8]) -> u64 {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    mix(hash)
+}
+
+/// The MurmurHash3 64-bit finalizer.
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+/// How well the SST filters of a database did since it was opened.
+#[derive(Clone, Debug, Default)]
+pub struct FilterStats {
+    /// `FilterPolicy::name` of the database's policy.
+    pub policy: String,
+    /// Point lookups a table's key filter was checked for.
+    pub checks: u64,
+    /// Checks the filter ruled the key out for, sparing a block read.
+    pub useful: u64,
+    /// Checks the filter let through that then found nothing in the table.
+    pub false_positives: u64,
+    /// Bytes taken by the filters of the live tables read so far.
+    pub memory_bytes: u64,
+}
+
+impl FilterStats {
+    /// Share of the lookups for keys a table doesn't hold that its filter
+    /// let through.
+    pub fn false_positive_rate(&self) -> f64 {
+        let negatives = self.useful + self.false_positives;
+        if negatives == 0 {
+            return 0.0;
+        }
+        self.false_positives as f64 / negatives as f64
+    }
+}
+
+/// The counters behind `FilterStats`, shared by the tables of a database.
+#[derive(Default)]
+pub struct FilterCounters {
+    checks: AtomicU64,
+    useful: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+impl FilterCounters {
+    /// Counts a check of a key filter, `passed` if it didn't rule the key
+    /// out.
+    pub fn record_check(&self, passed: bool) {
+        self.checks.fetch_add(1, Ordering::Relaxed);
+        if !passed {
+            self.useful.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a lookup the filter let through that found nothing.
+    pub fn record_false_positive(&self) {
+        self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self, policy: String, memory_bytes: u64) -> FilterStats {
+        FilterStats {
+            policy,
+            checks: self.checks.load(Ordering::Relaxed),
+            useful: self.useful.load(Ordering::Relaxed),
+            false_positives: self.false_positives.load(Ordering::Relaxed),
+            memory_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(range: std::ops::Range<u32>) -> Vec<Vec<u8>> {
+        range
+            .map(|i| format!("key-{:08}", i).into_bytes())
+            .collect()
+    }
+
+    /// Builds a filter over 10,000 keys at 10 bits per key, checks each of
+    /// them is let through and returns the share of 10,000 absent keys
+    /// that are too.
+    fn false_positive_rate(policy: &dyn FilterPolicy) -> f64 {
+        let present = keys(0..10_000);
+        let items: Vec<&[u8]> = present.iter().map(Vec::as_slice).collect();
+        let filter = policy.open(&policy.build(&items, 10)).unwrap();
+        for key in &present {
+            assert!(filter.may_contain(key), "false negative for {:?}", key);
+        }
+        let absent = keys(10_000..20_000);
+        let passed = absent.iter().filter(|key| filter.may_contain(key)).count();
+        passed as f64 / absent.len() as f64
+    }
+
+    #[test]
+    fn bloom_has_no_false_negatives() {
+        assert!(false_positive_rate(&BloomFilterPolicy) < 0.03);
+    }
+
+    #[test]
+    fn ribbon_has_no_false_negatives() {
+        assert!(false_positive_rate(&RibbonFilterPolicy) < 0.03);
+    }
+
+    #[test]
+    fn ribbon_filters_tiny_and_empty_sets() {
+        let policy = RibbonFilterPolicy;
+        for count in [0, 1, 2, 63, 64, 65] {
+            let present = keys(0..count);
+            let items: Vec<&[u8]> = present.iter().map(Vec::as_slice).collect();
+            let filter = policy.open(&policy.build(&items, 10)).unwrap();
+            assert!(present.iter().all(|key| filter.may_contain(key)));
+        }
+    }
+
+    #[test]
+    fn malformed_ribbon_filter_is_rejected() {
+        let policy = RibbonFilterPolicy;
+        let filter = policy.build(&[b"key"], 10);
+        assert!(policy.open(&filter[..filter.len() - 1]).is_none());
+        assert!(policy.open(&filter[..4]).is_none());
+        let mut too_many_bits = filter.clone();
+        too_many_bits[8] = 33;
+        assert!(policy.open(&too_many_bits).is_none());
+    }
+}
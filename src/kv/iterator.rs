@@ -0,0 +1,193 @@
+use super::{key::InternalKey, value::Value};
+use crate::errors::Result;
+use bytes::Bytes;
+use std::ops::{Bound, RangeBounds};
+
+/// Bounds of a scan over keys.
+pub type KeyRange = (Bound<Bytes>, Bound<Bytes>);
+
+pub fn to_key_range<K, R>(range: R) -> KeyRange
+where
+    K: AsRef<[u8]>,
+    R: RangeBounds<K>,
+{
+    let convert = |bound: Bound<&K>| match bound {
+        Bound::Included(key) => Bound::Included(Bytes::copy_from_slice(key.as_ref())),
+        Bound::Excluded(key) => Bound::Excluded(Bytes::copy_from_slice(key.as_ref())),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (convert(range.start_bound()), convert(range.end_bound()))
+}
+
+/// Borrows the bounds as byte slices, the form `SkipMap::range` takes.
+pub fn as_slices(range: &KeyRange) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    (
+        range.0.as_ref().map(|key| key.as_ref()),
+        range.1.as_ref().map(|key| key.as_ref()),
+    )
+}
+
+/// Whether `key` lies at or after the start of `range`.
+pub fn after_start(range: &KeyRange, key: &[u8]) -> bool {
+    match &range.0 {
+        Bound::Included(start) => key >= start.as_ref(),
+        Bound::Excluded(start) => key > start.as_ref(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether `key` lies at or before the end of `range`.
+pub fn before_end(range: &KeyRange, key: &[u8]) -> bool {
+    match &range.1 {
+        Bound::Included(end) => key <= end.as_ref(),
+        Bound::Excluded(end) => key < end.as_ref(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// The versions of a sorted run a scan merges, memtable or SST, in
+/// internal key order from either end. They are read as the scan advances;
+/// reading an SST block can fail.
+pub type Entries = Box<dyn DoubleEndedIterator<Item = Result<(InternalKey, Value)>> + Send>;
+
+/// A run being merged, with the entries peeked at either end.
+struct Run {
+    entries: Entries,
+    front: Option<(InternalKey, Value)>,
+    back: Option<(InternalKey, Value)>,
+}
+
+impl Run {
+    /// The next entry from the front, or the back if `reverse`, without
+    /// taking it.
+    fn peek(&mut self, reverse: bool) -> Result<Option<&(InternalKey, Value)>> {
+        let (head, other) = match reverse {
+            false => (&mut self.front, &mut self.back),
+            true => (&mut self.back, &mut self.front),
+        };
+        if head.is_none() {
+            let next = match reverse {
+                false => self.entries.next(),
+                true => self.entries.next_back(),
+            };
+            // Once the entries run out, the one peeked at the other end is
+            // the last left
+            *head = match next {
+                Some(entry) => Some(entry?),
+                None => other.take(),
+            };
+        }
+        Ok(head.as_ref())
+    }
+
+    /// Takes the entry `peek` returned.
+    fn pop(&mut self, reverse: bool) -> Option<(InternalKey, Value)> {
+        match reverse {
+            false => self.front.take(),
+            true => self.back.take(),
+        }
+    }
+}
+
+/// Iterates over the newest value, as of a sequence number, of every live
+/// key in a range, merged from the memtables and SST files. Iterating from
+/// the back (`.rev()`) walks the range in descending key order.
+///
+/// SST blocks are read as the iteration reaches them. A block that can't be
+/// read is yielded as an error, which ends the iteration.
+pub struct DBIterator {
+    runs: Vec<Run>,
+    seq: u64,
+    failed: bool,
+}
+
+impl DBIterator {
+    /// `sources` hold entries sorted by internal key and are ordered from
+    /// newest to oldest: where versions of a key share a sequence number,
+    /// as in tables written before writes had one, the first source wins.
+    /// Versions written after `seq` are skipped.
+    pub fn new(sources: Vec<Entries>, seq: u64) -> Self {
+        Self {
+            runs: sources
+                .into_iter()
+                .map(|entries| Run {
+                    entries,
+                    front: None,
+                    back: None,
+                })
+                .collect(),
+            seq,
+            failed: false,
+        }
+    }
+
+    /// Takes every version of the next key off the runs, returning the
+    /// newest one visible at `seq` (tombstones included), if any. `None`
+    /// once the runs are exhausted.
+    fn next_entry(&mut self, reverse: bool) -> Result<Option<Option<(InternalKey, Value)>>> {
+        let mut user_key: Option<Bytes> = None;
+        for run in &mut self.runs {
+            if let Some((head, _)) = run.peek(reverse)? {
+                let first = match &user_key {
+                    None => true,
+                    Some(key) if reverse => head.user_key > key,
+                    Some(key) => head.user_key < key,
+                };
+                if first {
+                    user_key = Some(head.user_key.clone());
+                }
+            }
+        }
+        let Some(user_key) = user_key else {
+            return Ok(None);
+        };
+
+        let mut newest: Option<(InternalKey, Value)> = None;
+        for run in &mut self.runs {
+            while run
+                .peek(reverse)?
+                .is_some_and(|(head, _)| head.user_key == user_key)
+            {
+                if let Some((key, value)) = run.pop(reverse) {
+                    let visible = key.seq <= self.seq;
+                    if visible && newest.as_ref().is_none_or(|(best, _)| key.seq > best.seq) {
+                        newest = Some((key, value));
+                    }
+                }
+            }
+        }
+        Ok(Some(newest))
+    }
+
+    /// The next live key from the front, or the back if `reverse`.
+    fn next_live(&mut self, reverse: bool) -> Option<Result<(Bytes, Bytes)>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            match self.next_entry(reverse) {
+                Ok(Some(Some((key, Value::Put(value))))) => return Some(Ok((key.user_key, value))),
+                Ok(Some(_)) => {}
+                Ok(None) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for DBIterator {
+    type Item = Result<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_live(false)
+    }
+}
+
+impl DoubleEndedIterator for DBIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_live(true)
+    }
+}
@@ -0,0 +1,57 @@
+use super::iterator::KeyRange;
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+/// Largest sequence number, reading at it sees every write.
+pub const MAX_SEQ: u64 = u64::MAX;
+
+/// A version of a user key: the key together with the sequence number of
+/// the write that produced it.
+///
+/// Versions order by user key, then newest first, so the newest version of
+/// a key is the first one found when seeking to it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InternalKey {
+    pub user_key: Bytes,
+    pub seq: u64,
+}
+
+impl InternalKey {
+    pub fn new(user_key: &[u8], seq: u64) -> Self {
+        Self {
+            user_key: Bytes::copy_from_slice(user_key),
+            seq,
+        }
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.user_key
+            .cmp(&other.user_key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Bounds of the internal keys holding every version of the user keys in
+/// `range`.
+pub fn internal_range(range: &KeyRange) -> (Bound<InternalKey>, Bound<InternalKey>) {
+    let start = match &range.0 {
+        Bound::Included(key) => Bound::Included(InternalKey::new(key, MAX_SEQ)),
+        Bound::Excluded(key) => Bound::Excluded(InternalKey::new(key, 0)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match &range.1 {
+        Bound::Included(key) => Bound::Included(InternalKey::new(key, 0)),
+        Bound::Excluded(key) => Bound::Excluded(InternalKey::new(key, MAX_SEQ)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (start, end)
+}
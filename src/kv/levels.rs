@@ -0,0 +1,416 @@
+use super::{
+    compaction::{self, Compaction, CompactionStats, CompactionStrategy, NUM_LEVELS},
+    filter::FilterStats,
+    iterator::KeyRange,
+    key::InternalKey,
+    manifest::{sst_path, FileMeta, Manifest},
+    options::Options,
+    snapshot::SnapshotList,
+    sst::{TableOptions, TableScan, SST},
+    value::Value,
+};
+use crate::errors::Result;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the single table written by versions before the MANIFEST existed.
+const LEGACY_SST_FILE: &str = "data.sst";
+
+/// An SST file of the database together with its manifest entry.
+#[derive(Clone)]
+pub struct Table {
+    pub meta: FileMeta,
+    pub sst: Arc<SST>,
+}
+
+/// The SST files of a database, organised in levels and tracked by the
+/// MANIFEST.
+///
+/// Flushed memtables land in L0, where files may overlap and are searched
+/// newest first. Files in L1 and below never overlap within their level.
+pub struct Levels {
+    dir: PathBuf,
+    manifest: Mutex<Manifest>,
+    tables: RwLock<Vec<Table>>,
+    strategy: Arc<dyn CompactionStrategy>,
+    table_options: TableOptions,
+    /// Compactions keep the versions these snapshots still read.
+    snapshots: Arc<SnapshotList>,
+    /// Compactions run one at a time so they never pick the same inputs.
+    compaction_lock: Mutex<()>,
+    stats: Mutex<CompactionStats>,
+}
+
+impl Levels {
+    /// Opens the SST files listed in the MANIFEST of `dir`, compacting them
+    /// with the strategy of `options` around the live `snapshots`.
+    ///
+    /// SST files that aren't part of the manifest are leftovers of a flush
+    /// that crashed before it was recorded, and are removed.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        options: &Options,
+        snapshots: Arc<SnapshotList>,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let manifest = match Manifest::load(&dir)? {
+            Some(manifest) => manifest,
+            None => Self::bootstrap(&dir)?,
+        };
+
+        let live: HashSet<PathBuf> = manifest
+            .files
+            .iter()
+            .map(|meta| sst_path(&dir, meta.id))
+            .collect();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let ext = path.extension().and_then(|ext| ext.to_str());
+            let orphan = match ext {
+                Some("sst") => !live.contains(&path),
+                Some("tmp") => true,
+                _ => false,
+            };
+            if orphan {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let table_options = TableOptions::from(options);
+        let mut tables = Vec::with_capacity(manifest.files.len());
+        for meta in &manifest.files {
+            let path = sst_path(&dir, meta.id);
+            if !path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("SST file {:?} listed in the MANIFEST is missing", path),
+                )
+                .into());
+            }
+            tables.push(Table {
+                meta: meta.clone(),
+                sst: Arc::new(SST::new(&path, &table_options)?),
+            });
+        }
+        Self::sort(&mut tables);
+
+        let strategy = Arc::clone(&options.compaction_strategy);
+        Ok(Self {
+            dir,
+            manifest: Mutex::new(manifest),
+            tables: RwLock::new(tables),
+            stats: Mutex::new(CompactionStats {
+                strategy: strategy.name(),
+                ..Default::default()
+            }),
+            strategy,
+            table_options,
+            snapshots,
+            compaction_lock: Mutex::new(()),
+        })
+    }
+
+    /// Creates the first manifest of a database, adopting the single
+    /// `data.sst` table of older versions if there is one.
+    fn bootstrap(dir: &Path) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+        let legacy_path = dir.join(LEGACY_SST_FILE);
+
+        let legacy = match fs::metadata(&legacy_path) {
+            Ok(metadata) if metadata.len() > 0 => {
+                Some(SST::new(&legacy_path, &TableOptions::default())?.entries()?)
+            }
+            Ok(_) => Some(Vec::new()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(entries) = &legacy {
+            if let (Some((smallest, _)), Some((largest, _))) = (entries.first(), entries.last()) {
+                // Link rather than rename, so the table isn't lost if we crash
+                // before the manifest referencing it is stored.
+                let id = manifest.new_file_id();
+                let path = sst_path(dir, id);
+                let _ = fs::remove_file(&path);
+                fs::hard_link(&legacy_path, &path)?;
+                let seq = manifest.new_seq();
+                manifest.files.push(FileMeta {
+                    id,
+                    level: 0,
+                    smallest: smallest.user_key.to_vec(),
+                    largest: largest.user_key.to_vec(),
+                    seq,
+                    size: fs::metadata(&path)?.len(),
+                });
+            }
+        }
+
+        manifest.store(dir)?;
+        if legacy.is_some() {
+            fs::remove_file(&legacy_path)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Keeps tables in the order they are searched: by level, newest first
+    /// within L0 and by key range in the other levels.
+    fn sort(tables: &mut [Table]) {
+        tables.sort_by(|a, b| {
+            a.meta.level.cmp(&b.meta.level).then_with(|| {
+                if a.meta.level == 0 {
+                    b.meta.seq.cmp(&a.meta.seq)
+                } else {
+                    a.meta.smallest.cmp(&b.meta.smallest)
+                }
+            })
+        });
+    }
+
+    /// Looks up the newest version of `key` written at or before `seq`, in
+    /// the newest table that holds one, returning its sequence number and
+    /// the stored value as is (tombstones included).
+    pub fn get(&self, key: &[u8], seq: u64) -> Result<Option<(u64, Value)>> {
+        for table in self.tables.read().iter() {
+            if !table.meta.contains(key) {
+                continue;
+            }
+            if let Some(found) = table.sst.get(key, seq)? {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Iterates over every version of the keys in `range` in every table
+    /// overlapping it, one sorted run per table in search order (tombstones
+    /// included).
+    ///
+    /// For a prefix scan, `prefix` lets tables whose prefix filter rules it
+    /// out be skipped.
+    pub fn scan(&self, range: &KeyRange, prefix: Option<&[u8]>) -> Result<Vec<TableScan>> {
+        let mut scans = Vec::new();
+        for table in self.tables.read().iter() {
+            if !table.meta.overlaps(range) {
+                continue;
+            }
+            if let Some(prefix) = prefix {
+                if !table.sst.may_contain_prefix(prefix)? {
+                    continue;
+                }
+            }
+            scans.push(table.sst.scan(range)?);
+        }
+        Ok(scans)
+    }
+
+    /// Writes `entries`, sorted by internal key, as a new L0 table and records it in
+    /// the manifest, along with `first_live_wal`: the WAL segments before it
+    /// only hold writes in SSTs now.
+    pub fn flush<I>(&self, entries: I, first_live_wal: u64) -> Result<()>
+    where
+        I: IntoIterator<Item = (InternalKey, Value)>,
+    {
+        let seq = self.manifest.lock().new_seq();
+        let table = self.write_table(0, seq, entries.into_iter().collect())?;
+        if let Some(table) = &table {
+            self.stats.lock().bytes_flushed += table.meta.size;
+        }
+        self.install_with(table.into_iter().collect(), &[], |manifest| {
+            manifest.first_live_wal = manifest.first_live_wal.max(first_live_wal);
+        })?;
+        Ok(())
+    }
+
+    /// First WAL segment recovery needs to replay.
+    pub fn first_live_wal(&self) -> u64 {
+        self.manifest.lock().first_live_wal
+    }
+
+    /// Writes `entries`, sorted by internal key, as a new table of `level`. The table
+    /// isn't part of the database until it is installed.
+    fn write_table(
+        &self,
+        level: usize,
+        seq: u64,
+        entries: Vec<(InternalKey, Value)>,
+    ) -> Result<Option<Table>> {
+        let (Some((smallest, _)), Some((largest, _))) = (entries.first(), entries.last()) else {
+            return Ok(None);
+        };
+        let (smallest, largest) = (smallest.user_key.to_vec(), largest.user_key.to_vec());
+
+        let id = self.manifest.lock().new_file_id();
+        let sst = SST::create(&sst_path(&self.dir, id), entries, &self.table_options)?;
+        let meta = FileMeta {
+            id,
+            level,
+            smallest,
+            largest,
+            seq,
+            size: sst.size()?,
+        };
+        Ok(Some(Table {
+            meta,
+            sst: Arc::new(sst),
+        }))
+    }
+
+    /// Atomically replaces the `removed` files by the `added` tables.
+    ///
+    /// The change only becomes visible to readers once the manifest
+    /// describing it is durable.
+    fn install(&self, added: Vec<Table>, removed: &[u64]) -> io::Result<()> {
+        self.install_with(added, removed, |_| {})
+    }
+
+    /// Installs the tables like `install`, applying `update` to the manifest
+    /// in the same change.
+    fn install_with<F>(&self, added: Vec<Table>, removed: &[u64], update: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Manifest),
+    {
+        let mut manifest = self.manifest.lock();
+        let mut next = manifest.clone();
+        update(&mut next);
+        next.files.retain(|meta| !removed.contains(&meta.id));
+        next.files
+            .extend(added.iter().map(|table| table.meta.clone()));
+        next.store(&self.dir)?;
+        *manifest = next;
+
+        let mut tables = self.tables.write();
+        tables.retain(|table| !removed.contains(&table.meta.id));
+        tables.extend(added);
+        Self::sort(&mut tables);
+        Ok(())
+    }
+
+    /// Largest sequence number stored in any table.
+    pub fn max_seq(&self) -> u64 {
+        self.tables
+            .read()
+            .iter()
+            .map(|table| table.sst.max_seq())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn stats(&self) -> CompactionStats {
+        self.stats.lock().clone()
+    }
+
+    pub fn filter_stats(&self) -> FilterStats {
+        let memory = self
+            .tables
+            .read()
+            .iter()
+            .map(|table| table.sst.filter_memory() as u64)
+            .sum();
+        let policy = self.table_options.filter_policy.name();
+        self.table_options.filter_stats.stats(policy, memory)
+    }
+
+    /// Runs the most urgent compaction, if any. Returns whether a compaction
+    /// ran.
+    pub fn maybe_compact(&self) -> Result<bool> {
+        let _compacting = self.compaction_lock.lock();
+        let picked = self.strategy.pick(&self.tables.read());
+        match picked {
+            Some(compaction) => self.run_compaction(compaction).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Compacts every file overlapping `[start, end]`, level by level, as
+    /// far down as the strategy takes them.
+    pub fn compact_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        let _compacting = self.compaction_lock.lock();
+        let mut compacted_into = None;
+        for level in 0..NUM_LEVELS {
+            let picked = self
+                .strategy
+                .pick_range(&self.tables.read(), level, start, end);
+            let Some(compaction) = picked else {
+                continue;
+            };
+            let in_place = compaction.output_level == level;
+            // The previous compaction already wrote this level without
+            // anything below it, there is nothing left to drop
+            if in_place && compacted_into == Some(level) {
+                break;
+            }
+            compacted_into = Some(compaction.output_level);
+            self.run_compaction(compaction)?;
+            if in_place {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_compaction(&self, compaction: Compaction) -> Result<()> {
+        let removed: Vec<u64> = compaction.inputs.iter().map(|t| t.meta.id).collect();
+        let seq = compaction
+            .inputs
+            .iter()
+            .map(|t| t.meta.seq)
+            .max()
+            .unwrap_or(0);
+        let oldest_input = compaction
+            .inputs
+            .iter()
+            .map(|t| t.meta.seq)
+            .min()
+            .unwrap_or(0);
+
+        // Files that may hold older versions of the merged keys: the levels
+        // below the output, and L0 files older than every input.
+        let older: Vec<FileMeta> = self
+            .tables
+            .read()
+            .iter()
+            .filter(|table| !removed.contains(&table.meta.id))
+            .filter(|table| {
+                table.meta.level > compaction.output_level
+                    || (table.meta.level == 0 && table.meta.seq < oldest_input)
+            })
+            .map(|table| table.meta.clone())
+            .collect();
+        let snapshots = self.snapshots.pinned();
+        let merged = compaction::merge(&compaction.inputs, &snapshots, |key| {
+            !older.iter().any(|meta| meta.contains(key))
+        })?;
+
+        // Outputs inherit the newest seq of their inputs, so they keep their
+        // place among the files they weren't merged with.
+        let mut outputs = Vec::new();
+        let written =
+            compaction::split(merged, compaction.target_file_size).try_for_each(|chunk| {
+                outputs.extend(self.write_table(compaction.output_level, seq, chunk?)?);
+                Ok(())
+            });
+        if let Err(e) = written {
+            // A damaged input may only be found once some outputs are written
+            for output in outputs {
+                let _ = fs::remove_file(sst_path(&self.dir, output.meta.id));
+            }
+            return Err(e);
+        }
+        {
+            let mut stats = self.stats.lock();
+            stats.compactions += 1;
+            stats.bytes_compacted += outputs.iter().map(|t| t.meta.size).sum::<u64>();
+        }
+        self.install(outputs, &removed)?;
+
+        // Readers still holding an input keep it mapped until they are done
+        for id in removed {
+            fs::remove_file(sst_path(&self.dir, id))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use crate::errors::{Result, ShortDBErrors};
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Row locks: a pessimistic transaction locks every key it reads for update
+/// or writes, and holds the locks until it ends. Every other write locks the
+/// keys it writes for as long as it takes to apply, so none of them can
+/// change a key a pessimistic transaction holds.
+///
+/// A transaction asking for a key locked by another one waits for it, at
+/// most for the lock timeout. Waits are tracked in a wait-for graph, and a
+/// wait that would close a cycle fails with `Deadlock` right away instead.
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    /// Signalled whenever locks are released.
+    released: Condvar,
+    timeout: Duration,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct LockTable {
+    /// The transaction holding each locked key.
+    owners: HashMap<Bytes, u64>,
+    /// The wait-for graph: the transaction each waiting transaction waits
+    /// for.
+    waits_for: HashMap<u64, u64>,
+}
+
+impl LockTable {
+    /// Whether `txn` waiting for `owner` would close a cycle.
+    fn would_deadlock(&self, txn: u64, owner: u64) -> bool {
+        let mut next = owner;
+        for _ in 0..=self.waits_for.len() {
+            if next == txn {
+                return true;
+            }
+            match self.waits_for.get(&next) {
+                Some(&waited) => next = waited,
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+impl LockManager {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            table: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+            timeout,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Id for a new transaction to lock keys under.
+    pub fn begin(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Locks `key` for `txn`, waiting for its holder to release it.
+    ///
+    /// Fails with `Deadlock` if the holder (transitively) waits for `txn`,
+    /// and with `LockTimeout` if the key is still locked after the timeout.
+    pub fn lock(&self, txn: u64, key: &[u8]) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let mut table = self.table.lock();
+        loop {
+            let owner = match table.owners.get(key) {
+                None => {
+                    table.owners.insert(Bytes::copy_from_slice(key), txn);
+                    break;
+                }
+                Some(&owner) if owner == txn => break,
+                Some(&owner) => owner,
+            };
+            // The holder may have changed since the last wake up, so the
+            // edge is checked again on every round
+            if table.would_deadlock(txn, owner) {
+                table.waits_for.remove(&txn);
+                return Err(ShortDBErrors::Deadlock);
+            }
+            table.waits_for.insert(txn, owner);
+            if self.released.wait_until(&mut table, deadline).timed_out()
+                && table.owners.get(key).is_some_and(|&owner| owner != txn)
+            {
+                table.waits_for.remove(&txn);
+                return Err(ShortDBErrors::LockTimeout);
+            }
+        }
+        table.waits_for.remove(&txn);
+        Ok(())
+    }
+
+    /// Locks every key of `keys` under a fresh id, in key order, for a
+    /// single write. Fails like `lock`, releasing the keys locked so far.
+    pub fn lock_all<'k, I>(&self, keys: I) -> Result<RowLocks<'_>>
+    where
+        I: IntoIterator<Item = &'k [u8]>,
+    {
+        let keys: BTreeSet<&[u8]> = keys.into_iter().collect();
+        let mut locked = RowLocks {
+            locks: self,
+            txn: self.begin(),
+            keys: Vec::with_capacity(keys.len()),
+        };
+        for key in keys {
+            self.lock(locked.txn, key)?;
+            locked.keys.push(Bytes::copy_from_slice(key));
+        }
+        Ok(locked)
+    }
+
+    /// Releases the locks `txn` holds on `keys`.
+    pub fn unlock<'k, I>(&self, txn: u64, keys: I)
+    where
+        I: IntoIterator<Item = &'k [u8]>,
+    {
+        let mut table = self.table.lock();
+        for key in keys {
+            if table.owners.get(key) == Some(&txn) {
+                table.owners.remove(key);
+            }
+        }
+        drop(table);
+        self.released.notify_all();
+    }
+}
+
+/// Locks taken by `LockManager::lock_all`, released when dropped.
+pub struct RowLocks<'a> {
+    locks: &'a LockManager,
+    txn: u64,
+    keys: Vec<Bytes>,
+}
+
+impl Drop for RowLocks<'_> {
+    fn drop(&mut self) {
+        self.locks
+            .unlock(self.txn, self.keys.iter().map(|key| key.as_ref()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn is_locked(locks: &LockManager, key: &[u8]) -> bool {
+        locks.table.lock().owners.contains_key(key)
+    }
+
+    /// Waits until `txn` is blocked on a lock.
+    fn wait_for_waiter(locks: &LockManager, txn: u64) {
+        while !locks.table.lock().waits_for.contains_key(&txn) {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn deadlock_cycle_fails_the_waiter_closing_it() {
+        let locks = LockManager::new(Duration::from_secs(10));
+        let (first, second) = (locks.begin(), locks.begin());
+        locks.lock(first, b"a").unwrap();
+        locks.lock(second, b"b").unwrap();
+
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| locks.lock(second, b"a"));
+            wait_for_waiter(&locks, second);
+            assert!(matches!(
+                locks.lock(first, b"b"),
+                Err(ShortDBErrors::Deadlock)
+            ));
+            // The other one goes through once the first gives up its keys
+            locks.unlock(first, [&b"a"[..]]);
+            waiting.join().unwrap().unwrap();
+        });
+        assert!(locks.table.lock().waits_for.is_empty());
+    }
+
+    #[test]
+    fn lock_times_out() {
+        let locks = LockManager::new(Duration::from_millis(50));
+        let (first, second) = (locks.begin(), locks.begin());
+        locks.lock(first, b"a").unwrap();
+        // Locking a key again is a no-op
+        locks.lock(first, b"a").unwrap();
+        assert!(matches!(
+            locks.lock(second, b"a"),
+            Err(ShortDBErrors::LockTimeout)
+        ));
+        locks.unlock(first, [&b"a"[..]]);
+        locks.lock(second, b"a").unwrap();
+    }
+
+    #[test]
+    fn row_locks_are_released_when_dropped() {
+        let locks = LockManager::new(Duration::from_millis(50));
+        let rows = locks.lock_all([&b"b"[..], b"a", b"b"]).unwrap();
+        assert!(is_locked(&locks, b"a") && is_locked(&locks, b"b"));
+        drop(rows);
+        assert!(!is_locked(&locks, b"a") && !is_locked(&locks, b"b"));
+
+        // A failed lock_all keeps none of its keys
+        let txn = locks.begin();
+        locks.lock(txn, b"b").unwrap();
+        assert!(matches!(
+            locks.lock_all([&b"a"[..], b"b"]),
+            Err(ShortDBErrors::LockTimeout)
+        ));
+        assert!(!is_locked(&locks, b"a"));
+    }
+}
@@ -0,0 +1,91 @@
+use super::iterator::{after_start, before_end, KeyRange};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+const MANIFEST_FILE: &str = "MANIFEST";
+
+/// Describes one SST file that is part of the database.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileMeta {
+    pub id: u64,
+    pub level: usize,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    /// Order in which the file was produced, a larger sequence holds newer
+    /// data. Overlapping files in L0 are searched newest first.
+    pub seq: u64,
+    pub size: u64,
+}
+
+impl FileMeta {
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.smallest.as_slice() <= key && key <= self.largest.as_slice()
+    }
+
+    pub fn overlaps(&self, range: &KeyRange) -> bool {
+        after_start(range, &self.largest) && before_end(range, &self.smallest)
+    }
+}
+
+/// The layout of the SST files on disk.
+///
+/// The manifest is rewritten as a whole on every change: the new version is
+/// written to a temporary file, fsynced and renamed over `MANIFEST`, so after
+/// a crash it always describes either the old or the new layout.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Manifest {
+    pub next_file_id: u64,
+    pub last_seq: u64,
+    pub files: Vec<FileMeta>,
+    /// First WAL segment holding writes that aren't in any SST yet; the
+    /// older ones are no longer needed by recovery.
+    pub first_live_wal: u64,
+}
+
+impl Manifest {
+    /// Reads the manifest in `dir`, `None` if the database doesn't have one yet.
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<Option<Self>> {
+        let path = dir.as_ref().join(MANIFEST_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let manifest = bincode::deserialize(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(manifest))
+    }
+
+    /// Atomically replaces the manifest in `dir` with `self`.
+    pub fn store<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, self).map_err(io::Error::other)?;
+        let tmp_file = writer.into_inner().map_err(|e| e.into_error())?;
+        tmp_file.sync_all()?;
+        drop(tmp_file);
+
+        fs::rename(&tmp_path, dir.join(MANIFEST_FILE))?;
+        File::open(dir)?.sync_all() // persist the rename itself
+    }
+
+    /// Hands out the id for a new SST file.
+    pub fn new_file_id(&mut self) -> u64 {
+        self.next_file_id += 1;
+        self.next_file_id
+    }
+
+    /// Hands out the sequence for a new SST file.
+    pub fn new_seq(&mut self) -> u64 {
+        self.last_seq += 1;
+        self.last_seq
+    }
+}
+
+/// Path of the SST file with the given id.
+pub fn sst_path<P: AsRef<Path>>(dir: P, id: u64) -> PathBuf {
+    dir.as_ref().join(format!("{:06}.sst", id))
+}
@@ -1,10 +1,19 @@
 // use anyhow::Result;
+use super::{
+    iterator::KeyRange,
+    key::{internal_range, InternalKey},
+    value::Value,
+};
 use crate::errors::{Result, ShortDBErrors};
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
-use std::sync::{Arc, Mutex};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// Every version written to a key is kept, under its own sequence number.
+#[derive(Default)]
 pub struct Memtable {
-    pub memtable: Arc<SkipMap<Bytes, Bytes>>,
+    pub memtable: Arc<SkipMap<InternalKey, Value>>,
     pub size: u64,
 }
 
@@ -16,23 +25,33 @@ impl Memtable {
         }
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        match self.memtable.get(key).map(|e| e.value().clone()) {
-            Some(v) if v == Bytes::copy_from_slice(b"tombstone") => Ok(None),
-            Some(v) => Ok(Some(v)),
+    /// Looks up the newest version of `key` written at or before `seq`.
+    pub fn get(&self, key: &[u8], seq: u64) -> Result<Option<Bytes>> {
+        match self.newest(key, seq) {
+            Some((_, value)) => Ok(value.as_put().cloned()),
             None => Err(ShortDBErrors::KeyNotFound),
         }
     }
 
-    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+    /// The newest version of `key` written at or before `seq`, along with
+    /// its sequence number.
+    pub fn newest(&self, key: &[u8], seq: u64) -> Option<(u64, Value)> {
+        self.memtable
+            .lower_bound(Bound::Included(&InternalKey::new(key, seq)))
+            .filter(|entry| entry.key().user_key.as_ref() == key)
+            .map(|entry| (entry.key().seq, entry.value().clone()))
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8], seq: u64) -> Result<()> {
         // Insert the key-value pair into the memtable
+        let key = InternalKey::new(key, seq);
         self.memtable
-            .insert(Bytes::copy_from_slice(&key), Bytes::copy_from_slice(value));
+            .insert(key.clone(), Value::Put(Bytes::copy_from_slice(value)));
         // let mut size = *self.size.lock().unwrap();
         self.size += 1;
         // dbg!(self.size);
         // Check if the insertion was successful
-        if self.memtable.get(key).is_some() {
+        if self.memtable.contains_key(&key) {
             if self.size >= 256 {
                 return Err(ShortDBErrors::FlushNeededFromMemTable); // Indicate that a flush is needed
             }
@@ -41,12 +60,10 @@ impl Memtable {
             Err(ShortDBErrors::ValueNotSet) // Use a meaningful error
         }
     }
-    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        //when we say we delete a key, we set its value to tombstone
-        self.memtable.insert(
-            Bytes::copy_from_slice(key),
-            Bytes::copy_from_slice(b"tombstone"),
-        );
+    pub fn delete(&mut self, key: &[u8], seq: u64) -> Result<()> {
+        //when we say we delete a key, we store a tombstone for it
+        self.memtable
+            .insert(InternalKey::new(key, seq), Value::Delete);
 
         // let mut size = *self.size.lock().unwrap();
         self.size += 1;
@@ -58,11 +75,64 @@ impl Memtable {
 
         Ok(())
     }
+    /// Inserts a version of `key` holding `value`, a put or a delete.
+    pub fn apply(&mut self, key: &[u8], value: &Value, seq: u64) -> Result<()> {
+        match value {
+            Value::Put(value) => self.set(key, value, seq),
+            Value::Delete => self.delete(key, seq),
+        }
+    }
+
+    /// Iterates over every version of the keys in `range`, in internal key
+    /// order (tombstones included).
+    pub fn scan(&self, range: &KeyRange) -> MemtableScan {
+        MemtableScan {
+            memtable: Arc::clone(&self.memtable),
+            range: internal_range(range),
+        }
+    }
+
     pub fn clear(&mut self) {
         self.memtable.clear();
         // *self.size.lock().unwrap() = 0;
         self.size = 0;
     }
 
-    pub fn freeze(&self) {}
+    /// Moves the current contents out into a memtable that is only read from
+    /// afterwards, leaving this one empty and ready to take writes again.
+    pub fn freeze(&mut self) -> Memtable {
+        std::mem::take(self)
+    }
+}
+
+/// Iterates over a range of a memtable, see `Memtable::scan`. It keeps the
+/// skiplist alive, and sees versions inserted after it was created; the
+/// range shrinks past every entry taken from either end.
+pub struct MemtableScan {
+    memtable: Arc<SkipMap<InternalKey, Value>>,
+    range: (Bound<InternalKey>, Bound<InternalKey>),
+}
+
+impl Iterator for MemtableScan {
+    type Item = (InternalKey, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.memtable.lower_bound(self.range.0.as_ref())?;
+        if !self.range.contains(entry.key()) {
+            return None;
+        }
+        self.range.0 = Bound::Excluded(entry.key().clone());
+        Some((entry.key().clone(), entry.value().clone()))
+    }
+}
+
+impl DoubleEndedIterator for MemtableScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.memtable.upper_bound(self.range.1.as_ref())?;
+        if !self.range.contains(entry.key()) {
+            return None;
+        }
+        self.range.1 = Bound::Excluded(entry.key().clone());
+        Some((entry.key().clone(), entry.value().clone()))
+    }
 }
@@ -1,4 +1,20 @@
+pub mod batch;
+pub mod block;
+pub mod compaction;
 pub mod db;
+pub mod filter;
+pub mod iterator;
+pub mod key;
+pub mod levels;
+pub mod lock;
+pub mod manifest;
 pub mod memtable;
+pub mod options;
+pub mod prefix;
+pub mod snapshot;
 pub mod sst;
+#[cfg(test)]
+mod testing;
+pub mod transaction;
+pub mod value;
 pub mod wal;
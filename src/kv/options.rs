@@ -0,0 +1,68 @@
+use super::{
+    compaction::{CompactionStrategy, LeveledCompaction},
+    filter::{BloomFilterPolicy, FilterPolicy},
+    prefix::PrefixExtractor,
+    wal::{RecoveryMode, SyncMode},
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings a database is opened with.
+#[derive(Clone)]
+pub struct Options {
+    /// Decides which SST files get compacted together, leveled by default.
+    pub compaction_strategy: Arc<dyn CompactionStrategy>,
+    /// Groups keys for `ShorterDB::scan_prefix`; with one set, every SST
+    /// gets a bloom filter over its key prefixes so prefix scans can skip
+    /// the files that hold none of them.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// How long a pessimistic transaction, or any other write, waits for a
+    /// key locked by another transaction before giving up with
+    /// `LockTimeout`.
+    pub lock_timeout: Duration,
+    /// When writes are fsynced to the WAL, unless a write picks its own
+    /// mode; every write by default.
+    pub sync_mode: SyncMode,
+    /// Size at which a WAL segment is sealed and the next one started,
+    /// besides whenever the memtable is frozen.
+    pub max_wal_segment_size: u64,
+    /// How damaged WAL records are dealt with when the database is opened;
+    /// by default only a torn tail is tolerated.
+    pub recovery_mode: RecoveryMode,
+    /// Size an SST data block is closed at; lookups read a whole block.
+    pub block_size: usize,
+    /// Entries between two keys stored in full in a data block, the rest
+    /// only store what they don't share with the key before them.
+    pub block_restart_interval: usize,
+    /// Checks the CRC of every SST data block a read goes through. When
+    /// off, only compactions check data blocks; the index, filter and
+    /// metadata blocks are checked whenever they are read either way.
+    pub verify_checksums: bool,
+    /// Builds the filters of every SST, bloom filters by default. SSTs
+    /// whose filters were built by another policy are searched without
+    /// them until compaction rewrites them.
+    pub filter_policy: Arc<dyn FilterPolicy>,
+    /// Bits the bloom filters of an SST spend per key, or per prefix for
+    /// its prefix filter: 10 rules out all but about 1% of absent keys.
+    /// Other filter policies aim for the same false positive rate. With 0,
+    /// tables are written without filters.
+    pub bloom_bits_per_key: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            compaction_strategy: Arc::new(LeveledCompaction::default()),
+            prefix_extractor: None,
+            lock_timeout: Duration::from_secs(1),
+            sync_mode: SyncMode::Always,
+            max_wal_segment_size: 16 << 20,
+            recovery_mode: RecoveryMode::TolerateCorruptedTail,
+            block_size: 4096,
+            block_restart_interval: 16,
+            verify_checksums: true,
+            filter_policy: Arc::new(BloomFilterPolicy),
+            bloom_bits_per_key: 10,
+        }
+    }
+}
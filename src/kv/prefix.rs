@@ -0,0 +1,126 @@
+use super::iterator::KeyRange;
+use bytes::Bytes;
+use std::ops::Bound;
+
+/// Maps keys to the prefix that groups them, e.g. `user:42:` for all the
+/// fields of `user:42:<field>`.
+///
+/// A prefix scan can only skip SST files through the prefix bloom filter
+/// when the scanned prefix is itself a complete prefix, i.e.
+/// `prefix(p) == Some(p)`; every key starting with such a `p` must then map
+/// to `p`.
+pub trait PrefixExtractor: Send + Sync {
+    /// The prefix of `key`, `None` for keys outside the extractor's domain.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+
+    /// Identifies the extractor along with its settings. SSTs remember the
+    /// name of the extractor their prefix filter was built with, and the
+    /// filter is only used while the database runs with the same one.
+    fn name(&self) -> String;
+}
+
+/// The first `len` bytes of every key at least that long.
+pub struct FixedPrefix(pub usize);
+
+impl PrefixExtractor for FixedPrefix {
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.0)
+    }
+
+    fn name(&self) -> String {
+        format!("fixed:{}", self.0)
+    }
+}
+
+/// Everything up to and including the `count`-th `delimiter`, so
+/// `DelimitedPrefix { delimiter: b':', count: 2 }` maps `user:42:name` to
+/// `user:42:`.
+pub struct DelimitedPrefix {
+    pub delimiter: u8,
+    pub count: usize,
+}
+
+impl PrefixExtractor for DelimitedPrefix {
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let (end, _) = key
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == self.delimiter)
+            .nth(self.count.checked_sub(1)?)?;
+        Some(&key[..=end])
+    }
+
+    fn name(&self) -> String {
+        format!("delimited:{}:{}", self.delimiter, self.count)
+    }
+}
+
+/// The range holding every key that starts with `prefix`.
+pub fn prefix_range(prefix: &[u8]) -> KeyRange {
+    let start = Bound::Included(Bytes::copy_from_slice(prefix));
+
+    // The end is the prefix with its last byte below 0xff incremented; a
+    // prefix made only of 0xff bytes runs until the end of the key space.
+    let end = match prefix.iter().rposition(|byte| *byte != 0xff) {
+        Some(last) => {
+            let mut end = prefix[..=last].to_vec();
+            end[last] += 1;
+            Bound::Excluded(Bytes::from(end))
+        }
+        None => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_prefix_needs_keys_long_enough() {
+        let extractor = FixedPrefix(4);
+        assert_eq!(extractor.prefix(b"user:42"), Some(&b"user"[..]));
+        assert_eq!(extractor.prefix(b"user"), Some(&b"user"[..]));
+        assert_eq!(extractor.prefix(b"use"), None);
+    }
+
+    #[test]
+    fn delimited_prefix_ends_at_the_nth_delimiter() {
+        let extractor = DelimitedPrefix {
+            delimiter: b':',
+            count: 2,
+        };
+        assert_eq!(extractor.prefix(b"user:42:name"), Some(&b"user:42:"[..]));
+        assert_eq!(extractor.prefix(b"user:42:"), Some(&b"user:42:"[..]));
+        assert_eq!(extractor.prefix(b"user:42"), None);
+        let none = DelimitedPrefix {
+            delimiter: b':',
+            count: 0,
+        };
+        assert_eq!(none.prefix(b"user:42:name"), None);
+    }
+
+    #[test]
+    fn prefix_range_ends_past_the_last_byte_below_0xff() {
+        let bytes = |bytes: &[u8]| Bytes::copy_from_slice(bytes);
+        assert_eq!(
+            prefix_range(b"ab"),
+            (Bound::Included(bytes(b"ab")), Bound::Excluded(bytes(b"ac")))
+        );
+        assert_eq!(
+            prefix_range(b"a\xff\xff"),
+            (
+                Bound::Included(bytes(b"a\xff\xff")),
+                Bound::Excluded(bytes(b"b"))
+            )
+        );
+        assert_eq!(
+            prefix_range(b"\xff\xff"),
+            (Bound::Included(bytes(b"\xff\xff")), Bound::Unbounded)
+        );
+        assert_eq!(
+            prefix_range(b""),
+            (Bound::Included(bytes(b"")), Bound::Unbounded)
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The sequence numbers pinned by live snapshots, which compaction must
+/// keep the versions of.
+#[derive(Default)]
+pub struct SnapshotList {
+    /// Number of live snapshots per pinned sequence number.
+    pinned: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    /// Takes a snapshot of the database as of `seq`.
+    pub fn pin(self: &Arc<Self>, seq: u64) -> Snapshot {
+        *self.pinned.lock().entry(seq).or_default() += 1;
+        Snapshot {
+            seq,
+            list: Arc::clone(self),
+        }
+    }
+
+    fn release(&self, seq: u64) {
+        let mut pinned = self.pinned.lock();
+        if let Some(count) = pinned.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(&seq);
+            }
+        }
+    }
+
+    /// The pinned sequence numbers, in ascending order.
+    pub fn pinned(&self) -> Vec<u64> {
+        self.pinned.lock().keys().copied().collect()
+    }
+}
+
+/// A frozen view of the database: reads through it only see the writes
+/// made before it was taken. Released when dropped.
+pub struct Snapshot {
+    seq: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// Sequence number of the last write the snapshot sees.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
@@ -157,238 +157,1195 @@
 //         .collect()
 // }
 
+use super::{
+    block::{Block, BlockBuilder},
+    filter::{FilterCounters, FilterPolicy, KeyFilter},
+    iterator::{after_start, before_end, KeyRange},
+    key::InternalKey,
+    options::Options,
+    prefix::PrefixExtractor,
+    value::Value,
+};
+use crate::errors::{Result, ShortDBErrors};
 use bincode;
-use bloomfilter::Bloom;
-use bytes::{Bytes, BytesMut};
-use crossbeam_channel::{bounded, Receiver, Sender};
-use memmap2::MmapMut;
-use parking_lot::RwLock;
+use bytes::Bytes;
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
-use std::fs::{File, OpenOptions};
-use std::io::{Seek, SeekFrom, Write};
+use std::cmp::{Ordering, Reverse};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-const BLOOM_FILTER_SIZE: usize = 1000000; // 100M items
-const BLOOM_FPR: f64 = 0.01; // 1% false positive rate
-const WRITE_BATCH_SIZE: usize = 1000;
 const INDEX_INTERVAL: usize = 1000000; // Create an index entry every 1000 entries
 
-#[derive(Serialize, Deserialize, Clone)]
-struct KeyValuePair {
+/// Starts every block-based table, its last byte is the format version.
+/// Tables without it were written before the block-based layout, and hold
+/// `LegacyKeyValuePair`s.
+const FORMAT_HEADER: &[u8; 8] = b"SDBSST\x00\x01";
+const MAGIC_LEN: usize = 7;
+
+/// Ends every block-based table, after the footer.
+const TABLE_MAGIC: u64 = 0x6c62_7473_7362_6473; // "sdbsstbl"
+const FOOTER_LEN: usize = 4 * 16 + 8;
+/// Length of the CRC32C following every block of a block-based table.
+const BLOCK_TRAILER_LEN: usize = 4;
+
+/// How SST files are built.
+#[derive(Clone)]
+pub struct TableOptions {
+    /// Size a data block is closed at.
+    pub block_size: usize,
+    /// Entries between two restart points of a data block.
+    pub restart_interval: usize,
+    /// Builds a bloom filter over the key prefixes of each table.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Checks the CRC of the data blocks reads go through, not only of
+    /// those compactions read.
+    pub verify_checksums: bool,
+    /// Builds the filters over the keys and prefixes of each table.
+    pub filter_policy: Arc<dyn FilterPolicy>,
+    /// Bits of bloom filter per key, none written if 0.
+    pub bloom_bits_per_key: usize,
+    /// Counts how the key filters of the tables did.
+    pub filter_stats: Arc<FilterCounters>,
+}
+
+impl From<&Options> for TableOptions {
+    fn from(options: &Options) -> Self {
+        Self {
+            block_size: options.block_size,
+            restart_interval: options.block_restart_interval,
+            prefix_extractor: options.prefix_extractor.clone(),
+            verify_checksums: options.verify_checksums,
+            filter_policy: Arc::clone(&options.filter_policy),
+            bloom_bits_per_key: options.bloom_bits_per_key,
+            filter_stats: Arc::default(),
+        }
+    }
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self::from(&Options::default())
+    }
+}
+
+/// Entry of a table written before deletes were typed, a delete is stored
+/// as the value `b"tombstone"`.
+#[derive(Serialize, Deserialize)]
+struct LegacyKeyValuePair {
     key: Vec<u8>,
     value: Vec<u8>,
     timestamp: u64,
 }
 
+impl LegacyKeyValuePair {
+    /// The entry, with sequence number 0 as it predates them.
+    fn into_entry(self) -> (InternalKey, Value) {
+        let key = InternalKey::new(&self.key, 0);
+        (key, Value::from_legacy(Bytes::from(self.value)))
+    }
+}
+
+/// Layout of the entries in a table file. A legacy table is a flat stream
+/// of bincode entries, which is scanned as a whole when the table is
+/// opened; it is still read, but tables are only written block-based.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Legacy,
+    /// Data blocks followed by the filter, metadata and index blocks and a
+    /// footer locating them, each with a CRC32C after it, see `SST::create`.
+    Block,
+}
+
+impl Format {
+    /// Tells the format of a table from its first bytes, `None` for a
+    /// version this build doesn't know. An empty file is read as an empty
+    /// legacy table.
+    fn detect(data: &[u8]) -> Option<Self> {
+        match data.strip_prefix(&FORMAT_HEADER[..MAGIC_LEN]) {
+            Some([1, ..]) => Some(Format::Block),
+            Some(_) => None,
+            None => Some(Format::Legacy),
+        }
+    }
+
+    /// Offset of the first entry.
+    fn data_start(self, len: usize) -> usize {
+        match self {
+            Format::Legacy => 0,
+            _ => FORMAT_HEADER.len().min(len),
+        }
+    }
+
+    fn is_block_based(self) -> bool {
+        self == Format::Block
+    }
+
+    /// Decodes the entry at the start of `data`, along with its encoded size.
+    fn decode(self, data: &[u8]) -> Option<((InternalKey, Value), usize)> {
+        match self {
+            Format::Legacy => {
+                let kv = bincode::deserialize::<LegacyKeyValuePair>(data).ok()?;
+                let size = bincode::serialized_size(&kv).ok()? as usize;
+                Some((kv.into_entry(), size))
+            }
+            // Block-based tables aren't read entry by entry
+            Format::Block => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 struct IndexEntry {
     key: Vec<u8>,
     position: u64,
 }
 
+/// Location of a block in a table file.
+#[derive(Clone, Copy, Default)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    fn slice<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let start = usize::try_from(self.offset).ok()?;
+        data.get(start..start.checked_add(usize::try_from(self.size).ok()?)?)
+    }
+}
+
+/// The fixed-size end of a block-based table, locating its other blocks:
+/// four `[offset: u64][size: u64]` handles followed by `TABLE_MAGIC`. An
+/// empty filter handle means the table has no such filter.
+struct Footer {
+    index: BlockHandle,
+    filter: BlockHandle,
+    prefix_filter: BlockHandle,
+    meta: BlockHandle,
+}
+
+impl Footer {
+    fn encode(&self) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(FOOTER_LEN);
+        for handle in [self.index, self.filter, self.prefix_filter, self.meta] {
+            footer.extend_from_slice(&handle.offset.to_le_bytes());
+            footer.extend_from_slice(&handle.size.to_le_bytes());
+        }
+        footer.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
+        footer
+    }
+
+    /// Reads the footer at the end of `data`, `None` if it is missing.
+    fn decode(data: &[u8]) -> Option<Self> {
+        let footer = data.get(data.len().checked_sub(FOOTER_LEN)?..)?;
+        let word = |i: usize| u64::from_le_bytes(footer[8 * i..8 * (i + 1)].try_into().unwrap());
+        if word(8) != TABLE_MAGIC {
+            return None;
+        }
+        let handle = |i: usize| BlockHandle {
+            offset: word(2 * i),
+            size: word(2 * i + 1),
+        };
+        Some(Self {
+            index: handle(0),
+            filter: handle(1),
+            prefix_filter: handle(2),
+            meta: handle(3),
+        })
+    }
+}
+
+/// Entry of the index block: the last key in a data block, and the block.
+#[derive(Serialize, Deserialize, Clone)]
+struct BlockIndexEntry {
+    last_key: Vec<u8>,
+    last_seq: u64,
+    offset: u64,
+    size: u64,
+}
+
+impl BlockIndexEntry {
+    fn handle(&self) -> BlockHandle {
+        BlockHandle {
+            offset: self.offset,
+            size: self.size,
+        }
+    }
+
+    /// Whether every entry of the block orders before `(key, seq)`.
+    fn is_before(&self, key: &[u8], seq: u64) -> bool {
+        (self.last_key.as_slice(), Reverse(self.last_seq)) < (key, Reverse(seq))
+    }
+}
+
+/// Metadata block of a block-based table.
+#[derive(Serialize, Deserialize)]
+struct TableMeta {
+    entries: u64,
+    max_seq: u64,
+    /// Seconds since the epoch the table was written at.
+    created: u64,
+    /// `PrefixExtractor::name` of the extractor the prefix filter was built
+    /// with.
+    prefix_extractor: Option<String>,
+    /// `FilterPolicy::name` of the policy the filters were built with.
+    filter_policy: Option<String>,
+}
+
+/// A filter of a table. Block-based tables read theirs the first time it
+/// is checked rather than when they are opened.
+struct Filter {
+    /// Where the filter is stored, `None` for one built as the table was
+    /// opened.
+    handle: Option<BlockHandle>,
+    filter: OnceLock<Box<dyn KeyFilter>>,
+}
+
+impl Filter {
+    fn stored(handle: BlockHandle) -> Option<Self> {
+        (handle.size > 0).then(|| Self {
+            handle: Some(handle),
+            filter: OnceLock::new(),
+        })
+    }
+
+    fn built(filter: Box<dyn KeyFilter>) -> Self {
+        Self {
+            handle: None,
+            filter: OnceLock::from(filter),
+        }
+    }
+}
+
+/// The encoded filter of the `options` policy holding `items`; `None` if
+/// tables get no filters. Equal items must be adjacent.
+fn build_filter<'a, I>(items: I, options: &TableOptions) -> Option<Vec<u8>>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    if options.bloom_bits_per_key == 0 {
+        return None;
+    }
+    let mut items: Vec<&[u8]> = items.into_iter().collect();
+    items.dedup();
+    Some(
+        options
+            .filter_policy
+            .build(&items, options.bloom_bits_per_key),
+    )
+}
+
+/// Filter over the key prefixes of a table, so prefix scans can skip
+/// tables that hold no key with their prefix.
+struct PrefixFilter {
+    extractor: Arc<dyn PrefixExtractor>,
+    filter: Filter,
+}
+
+/// An SST file. Tables are never written to once created: flushes and
+/// compactions write new ones, see `Levels`.
 pub struct SST {
-    file: Arc<RwLock<File>>,
-    mmap: Arc<RwLock<MmapMut>>,
-    bloom_filter: Arc<RwLock<Bloom<Vec<u8>>>>,
-    write_queue: (Sender<KeyValuePair>, Receiver<KeyValuePair>),
-    index: Arc<RwLock<Vec<IndexEntry>>>,
+    reader: Arc<TableReader>,
 }
 
 impl SST {
-    pub fn new(path: &Path) -> Self {
-        let file = OpenOptions::new()
-            .read(true)
+    /// Opens the table at `path`, creating an empty one if there is none.
+    /// A block-based table only has its index and metadata blocks read, its
+    /// filters are read when first checked; a flat one is scanned to rebuild
+    /// its index and bloom filters, sized after the keys it holds.
+    ///
+    /// Fails with `Corruption` if those blocks, or the entries of a flat
+    /// table, can't be read back.
+    pub fn new(path: &Path, options: &TableOptions) -> Result<Self> {
+        OpenOptions::new()
             .write(true)
             .create(true)
-            .open(path);
+            .truncate(false)
+            .open(path)?;
+        let reader = TableReader::open(path, options)?;
+
+        Ok(SST {
+            reader: Arc::new(reader),
+        })
+    }
+
+    /// Looks up the newest version of `key` written at or before `seq`,
+    /// returning its sequence number and the stored value as is (tombstones
+    /// included).
+    ///
+    /// Fails with `Corruption` if a block it reads is damaged, checking
+    /// data block CRCs only if the table was opened to verify them.
+    pub fn get(&self, key: &[u8], seq: u64) -> Result<Option<(u64, Value)>> {
+        self.reader.get(key, seq)
+    }
 
-        let file = match file {
-            Ok(f) => f,
+    /// Whether the table may hold keys starting with `prefix`. Only answers
+    /// `false` when `prefix` is a complete prefix of its extractor and the
+    /// prefix bloom filter rules it out. Fails with `Corruption` if the
+    /// filter, read on first use, is damaged.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> Result<bool> {
+        self.reader.may_contain_prefix(prefix)
+    }
+
+    /// Iterates over every version of the keys in `range`, in internal key
+    /// order (tombstones included), reading a data block at a time. A
+    /// damaged block fails like `get` once the iteration reaches it.
+    ///
+    /// Tables written before the block-based layout are read whole, failing
+    /// here if they are damaged.
+    pub fn scan(&self, range: &KeyRange) -> Result<TableScan> {
+        self.scan_checked(range, self.reader.verify_checksums)
+    }
+
+    /// Iterates over every entry stored in the table, like `scan`. The CRCs
+    /// of the data blocks are always checked, as compactions rewrite what
+    /// this returns.
+    pub fn verified_scan(&self) -> Result<TableScan> {
+        self.scan_checked(&(Bound::Unbounded, Bound::Unbounded), true)
+    }
+
+    fn scan_checked(&self, range: &KeyRange, verify: bool) -> Result<TableScan> {
+        let reader = Arc::clone(&self.reader);
+        let (blocks, front) = if reader.format.is_block_based() {
+            (reader.blocks_overlapping(range), VecDeque::new())
+        } else {
+            (0..0, VecDeque::from(reader.flat_scan(range)?))
+        };
+        Ok(TableScan {
+            reader,
+            range: range.clone(),
+            verify,
+            blocks,
+            front,
+            back: VecDeque::new(),
+        })
+    }
+
+    /// Writes `entries` as a new block-based table at `path` and opens it.
+    ///
+    /// `entries` must be sorted by internal key; tombstones are kept so they keep
+    /// shadowing older tables. See `write_table` for the layout.
+    pub fn create<I>(path: &Path, entries: I, options: &TableOptions) -> Result<Self>
+    where
+        I: IntoIterator<Item = (InternalKey, Value)>,
+    {
+        write_table(path, entries, options)?;
+        Self::new(path, options)
+    }
+
+    /// Bytes of memory taken by the table's filters, counting only those
+    /// read so far.
+    pub fn filter_memory(&self) -> usize {
+        self.reader.filter_memory()
+    }
+
+    /// Largest sequence number stored in the table, 0 for tables written
+    /// before writes had one.
+    pub fn max_seq(&self) -> u64 {
+        self.reader.max_seq
+    }
+
+    /// Size of the table file in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        Ok(self.reader.file.metadata()?.len())
+    }
+
+    /// Reads every entry stored in the table, in internal key order,
+    /// checking the CRCs of the data blocks.
+    pub fn entries(&self) -> Result<Vec<(InternalKey, Value)>> {
+        self.verified_scan()?.collect()
+    }
+}
+
+/// Iterates over a range of an SST, see `SST::scan`. It holds the table
+/// open, so it keeps reading it even once compaction replaced it.
+pub struct TableScan {
+    reader: Arc<TableReader>,
+    range: KeyRange,
+    /// Whether the CRCs of the data blocks are checked.
+    verify: bool,
+    /// Data blocks neither end has read yet.
+    blocks: std::ops::Range<usize>,
+    /// Entries of the block read last from the front, and from the back.
+    front: VecDeque<(InternalKey, Value)>,
+    back: VecDeque<(InternalKey, Value)>,
+}
+
+impl TableScan {
+    /// Reads the block at `index` into `front`, or `back` if `reverse`.
+    /// After a failure nothing else is read.
+    fn load(&mut self, index: usize, reverse: bool) -> Result<()> {
+        match self
+            .reader
+            .read_block_range(index, &self.range, self.verify)
+        {
+            Ok(entries) if reverse => self.back = entries,
+            Ok(entries) => self.front = entries,
             Err(e) => {
-                eprintln!("Error opening file: {:?}", e);
-                eprintln!("Path: {:?}", path);
-                eprintln!("Current working directory: {:?}", std::env::current_dir());
-                panic!();
+                self.blocks = 0..0;
+                self.front.clear();
+                self.back.clear();
+                return Err(e);
             }
-        };
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for TableScan {
+    type Item = Result<(InternalKey, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.front.pop_front() {
+                return Some(Ok(entry));
+            }
+            // The block the back end read last is all that's left
+            let Some(index) = self.blocks.next() else {
+                return self.back.pop_front().map(Ok);
+            };
+            if let Err(e) = self.load(index, false) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for TableScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.back.pop_back() {
+                return Some(Ok(entry));
+            }
+            let Some(index) = self.blocks.next_back() else {
+                return self.front.pop_back().map(Ok);
+            };
+            if let Err(e) = self.load(index, true) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Writes `entries`, sorted by internal key, as a block-based table at
+/// `path`. The table is written to a temporary file, fsynced and only then
+/// renamed into place, so a crash never leaves a half written table behind.
+///
+/// After the header come the data blocks, then the filter over the table's
+/// keys, the prefix filter if there is a prefix extractor (neither if
+/// `bloom_bits_per_key` is 0), the metadata block, the index block holding
+/// the last key of every data block, and the footer. Every block is
+/// followed by its CRC32C.
+fn write_table<I>(path: &Path, entries: I, options: &TableOptions) -> io::Result<()>
+where
+    I: IntoIterator<Item = (InternalKey, Value)>,
+{
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let tmp_path = path.with_extension("sst.tmp");
+    let mut writer = TableWriter {
+        writer: BufWriter::new(File::create(&tmp_path)?),
+        offset: 0,
+    };
+    writer.write(FORMAT_HEADER)?;
+
+    let mut builder = BlockBuilder::new(options.restart_interval);
+    let mut blocks = Vec::new();
+    let mut keys: Vec<Bytes> = Vec::new();
+    let mut meta = TableMeta {
+        entries: 0,
+        max_seq: 0,
+        created,
+        prefix_extractor: None,
+        filter_policy: None,
+    };
+    let mut last = None;
+    for (key, value) in entries {
+        builder.add(&key, &value);
+        if keys.last() != Some(&key.user_key) {
+            keys.push(key.user_key.clone());
+        }
+        meta.entries += 1;
+        meta.max_seq = meta.max_seq.max(key.seq);
+        last = Some(key);
+        if builder.estimated_size() >= options.block_size {
+            blocks.push(writer.write_data_block(&builder.finish(), last.as_ref())?);
+        }
+    }
+    if !builder.is_empty() {
+        blocks.push(writer.write_data_block(&builder.finish(), last.as_ref())?);
+    }
+
+    let mut filter = BlockHandle::default();
+    if let Some(encoded) = build_filter(keys.iter().map(|key| key.as_ref()), options) {
+        filter = writer.write_block(&encoded)?;
+        meta.filter_policy = Some(options.filter_policy.name());
+    }
+
+    let mut prefix_filter = BlockHandle::default();
+    if let Some(extractor) = &options.prefix_extractor {
+        let prefixes = keys.iter().filter_map(|key| extractor.prefix(key));
+        if let Some(encoded) = build_filter(prefixes, options) {
+            prefix_filter = writer.write_block(&encoded)?;
+            meta.prefix_extractor = Some(extractor.name());
+            meta.filter_policy = Some(options.filter_policy.name());
+        }
+    }
+
+    let footer = Footer {
+        meta: writer.write_serialized(&meta)?,
+        index: writer.write_serialized(&blocks)?,
+        filter,
+        prefix_filter,
+    };
+    writer.write(&footer.encode())?;
+
+    let tmp_file = writer.writer.into_inner().map_err(|e| e.into_error())?;
+    tmp_file.sync_all()?;
+    drop(tmp_file);
+
+    fs::rename(&tmp_path, path)?;
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?; // persist the rename itself
+    }
+
+    Ok(())
+}
 
-        let mmap = unsafe { MmapMut::map_mut(&file).unwrap() };
-        let bloom_filter = Bloom::new_for_fp_rate(BLOOM_FILTER_SIZE, BLOOM_FPR);
-        let (sender, receiver) = bounded(WRITE_BATCH_SIZE);
+/// A table file opened for reading. It never changes once opened; writes to
+/// the table open a new one.
+struct TableReader {
+    path: PathBuf,
+    file: File,
+    mmap: Mmap,
+    format: Format,
+    key_filter: Option<Filter>,
+    prefix_filter: Option<PrefixFilter>,
+    filter_policy: Arc<dyn FilterPolicy>,
+    filter_stats: Arc<FilterCounters>,
+    /// Largest sequence number stored in the table.
+    max_seq: u64,
+    /// Sparse index of a flat table.
+    index: Vec<IndexEntry>,
+    /// Index block of a block-based table.
+    blocks: Vec<BlockIndexEntry>,
+    verify_checksums: bool,
+}
 
-        let sst = SST {
-            file: Arc::new(RwLock::new(file)),
-            mmap: Arc::new(RwLock::new(mmap)),
-            bloom_filter: Arc::new(RwLock::new(bloom_filter)),
-            write_queue: (sender, receiver),
-            index: Arc::new(RwLock::new(Vec::new())),
+impl TableReader {
+    fn open(path: &Path, options: &TableOptions) -> Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let format = Format::detect(&mmap).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("SST {:?} has an unsupported format version", path),
+            )
+        })?;
+        let mut reader = TableReader {
+            path: path.to_path_buf(),
+            file,
+            mmap,
+            format,
+            key_filter: None,
+            prefix_filter: None,
+            filter_policy: Arc::clone(&options.filter_policy),
+            filter_stats: Arc::clone(&options.filter_stats),
+            max_seq: 0,
+            index: Vec::new(),
+            blocks: Vec::new(),
+            verify_checksums: options.verify_checksums,
         };
+        if format.is_block_based() {
+            reader.load_block_table(options)?;
+        } else {
+            reader.load_index_and_filters(options)?;
+        }
+        Ok(reader)
+    }
 
-        sst.start_write_thread();
-        sst.load_index_and_bloom_filter();
-        sst
-    }
-
-    fn start_write_thread(&self) {
-        let receiver = self.write_queue.1.clone();
-        let mmap = Arc::clone(&self.mmap);
-        let bloom_filter = Arc::clone(&self.bloom_filter);
-        let index = Arc::clone(&self.index);
-
-        std::thread::spawn(move || {
-            let mut batch = Vec::with_capacity(WRITE_BATCH_SIZE);
-            loop {
-                // dbg!("hi im in a loop");
-                while let Ok(kv) = receiver.try_recv() {
-                    batch.push(kv);
-                    if batch.len() >= WRITE_BATCH_SIZE {
-                        Self::write_batch(&mmap, &bloom_filter, &index, &batch);
-                        batch.clear();
-                    }
-                }
-                if !batch.is_empty() {
-                    Self::write_batch(&mmap, &bloom_filter, &index, &batch);
-                    batch.clear();
-                }
-                std::thread::sleep(std::time::Duration::from_millis(10));
+    /// Reads the index and metadata blocks of a block-based table, and
+    /// locates its filters.
+    fn load_block_table(&mut self, options: &TableOptions) -> Result<()> {
+        let footer = Footer::decode(&self.mmap)
+            .ok_or_else(|| self.corruption(self.mmap.len().saturating_sub(FOOTER_LEN)))?;
+        self.blocks = self.read_serialized(footer.index)?;
+        let meta: TableMeta = self.read_serialized(footer.meta)?;
+        self.max_seq = meta.max_seq;
+
+        // Filters of another policy can't be read, the table is then
+        // searched for every key until it is rewritten
+        if meta.filter_policy != Some(self.filter_policy.name()) {
+            return Ok(());
+        }
+        self.key_filter = Filter::stored(footer.filter);
+
+        // A filter built by another extractor could rule out prefixes the
+        // table holds, the table is then scanned for every prefix
+        self.prefix_filter = match (&options.prefix_extractor, meta.prefix_extractor) {
+            (Some(extractor), Some(name)) if extractor.name() == name => {
+                Filter::stored(footer.prefix_filter).map(|filter| PrefixFilter {
+                    extractor: Arc::clone(extractor),
+                    filter,
+                })
             }
-        });
+            _ => None,
+        };
+        Ok(())
     }
 
-    fn load_index_and_bloom_filter(&self) {
-        let mmap = self.mmap.read();
-        let mut position = 0;
+    fn load_index_and_filters(&mut self, options: &TableOptions) -> Result<()> {
+        let mut position = self.format.data_start(self.mmap.len());
         let mut index_counter = 0;
+        // Entries are sorted by key, so the versions of a key are adjacent
+        let mut keys: Vec<Bytes> = Vec::new();
 
-        while position < mmap.len() {
-            match bincode::deserialize::<KeyValuePair>(&mmap[position..]) {
-                Ok(kv) => {
-                    self.bloom_filter.write().set(&kv.key);
+        while position < self.mmap.len() {
+            match self.format.decode(&self.mmap[position..]) {
+                Some(((key, _), size)) => {
+                    self.max_seq = self.max_seq.max(key.seq);
+                    if keys.last() != Some(&key.user_key) {
+                        keys.push(key.user_key.clone());
+                    }
 
                     if index_counter % INDEX_INTERVAL == 0 {
-                        self.index.write().push(IndexEntry {
-                            key: kv.key.clone(),
+                        self.index.push(IndexEntry {
+                            key: key.user_key.to_vec(),
                             position: position as u64,
                         });
                     }
 
-                    position += bincode::serialized_size(&kv).unwrap() as usize;
+                    position += size;
                     index_counter += 1;
                 }
-                Err(_) => break, // Handle deserialization error
+                None => {
+                    self.flat_end(position)?;
+                    break;
+                }
             }
         }
+
+        if let Some(encoded) = build_filter(keys.iter().map(|key| &key[..]), options) {
+            self.key_filter = Some(Filter::built(self.open_filter(&encoded, 0)?));
+        }
+        if let Some(extractor) = &options.prefix_extractor {
+            let prefixes = keys.iter().filter_map(|key| extractor.prefix(key));
+            if let Some(encoded) = build_filter(prefixes, options) {
+                self.prefix_filter = Some(PrefixFilter {
+                    extractor: Arc::clone(extractor),
+                    filter: Filter::built(self.open_filter(&encoded, 0)?),
+                });
+            }
+        }
+        Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        dbg!("really checking sst?");
-        if !self.bloom_filter.read().check(&key.to_vec()) {
-            dbg!("Bloom filter says key is not present");
-            return None;
+    fn corruption(&self, offset: usize) -> ShortDBErrors {
+        ShortDBErrors::Corruption {
+            file: self.path.clone(),
+            offset: offset as u64,
+        }
+    }
+
+    /// The block at `handle`, checking its CRC first if `verify` is set.
+    fn read_block(&self, handle: BlockHandle, verify: bool) -> Result<&[u8]> {
+        let stored = BlockHandle {
+            offset: handle.offset,
+            size: handle.size.saturating_add(BLOCK_TRAILER_LEN as u64),
+        };
+        let corruption = || self.corruption(handle.offset as usize);
+        let stored = stored.slice(&self.mmap).ok_or_else(corruption)?;
+        let (block, crc) = stored.split_at(stored.len() - BLOCK_TRAILER_LEN);
+        if verify && crc32c::crc32c(block).to_le_bytes() != crc {
+            return Err(corruption());
         }
+        Ok(block)
+    }
 
-        let mmap = self.mmap.read();
-        let index = self.index.read();
+    /// Reads one of the bincode blocks following the data blocks; these are
+    /// always verified, as they are read at most once per opened table.
+    fn read_serialized<T>(&self, handle: BlockHandle) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        bincode::deserialize(self.read_block(handle, true)?)
+            .map_err(|_| self.corruption(handle.offset as usize))
+    }
 
-        // Binary search in the index
-        let search_result = index.binary_search_by(|entry| entry.key.as_slice().cmp(key));
-        let start_position = match search_result {
-            Ok(exact_match) => index[exact_match].position,
-            Err(insertion_point) if insertion_point > 0 => index[insertion_point - 1].position,
-            _ => {
-                dbg!("Index search failed");
-                return None;
-            }
+    fn read_data_block(&self, entry: &BlockIndexEntry, verify: bool) -> Result<Block<'_>> {
+        Block::new(self.read_block(entry.handle(), verify)?)
+            .ok_or_else(|| self.corruption(entry.offset as usize))
+    }
+
+    /// Where an entry of a flat table fails to decode: the end of the
+    /// table if only zeros follow, corruption otherwise.
+    fn flat_end(&self, position: usize) -> Result<()> {
+        match self.mmap[position..].iter().all(|&byte| byte == 0) {
+            true => Ok(()),
+            false => Err(self.corruption(position)),
+        }
+    }
+
+    fn get(&self, key: &[u8], seq: u64) -> Result<Option<(u64, Value)>> {
+        let Some(filter) = &self.key_filter else {
+            return self.find(key, seq);
         };
+        let passed = self.check_filter(filter, key)?;
+        self.filter_stats.record_check(passed);
+        if !passed {
+            return Ok(None);
+        }
+        let found = self.find(key, seq)?;
+        if found.is_none() {
+            self.filter_stats.record_false_positive();
+        }
+        Ok(found)
+    }
+
+    /// Looks `key` up past the filters.
+    fn find(&self, key: &[u8], seq: u64) -> Result<Option<(u64, Value)>> {
+        if self.format.is_block_based() {
+            return self.block_get(key, seq);
+        }
 
-        let mut position = start_position as usize;
-        let i: usize = 0;
-        dbg!(position);
-        dbg!(mmap.len());
-        while position <= mmap.len() {
-            match bincode::deserialize::<KeyValuePair>(&mmap[position..]) {
-                Ok(kv) => {
-                    dbg!(kv.key.as_slice());
-                    dbg!(key);
-                    match kv.key.as_slice().cmp(key) {
-                        Ordering::Equal => return Some(Bytes::from(kv.value)),
+        let mut position = self.seek(key);
+        while position < self.mmap.len() {
+            match self.format.decode(&self.mmap[position..]) {
+                Some(((entry_key, value), size)) => {
+                    match entry_key.user_key.as_ref().cmp(key) {
+                        Ordering::Equal if entry_key.seq <= seq => {
+                            return Ok(Some((entry_key.seq, value)));
+                        }
+                        Ordering::Equal => {}
                         Ordering::Greater => break,
                         Ordering::Less => {}
                     }
-                    position += bincode::serialized_size(&kv).unwrap() as usize;
+                    position += size;
                 }
-                Err(e) => {
-                    println!("error:{}", e);
-                    dbg!("though here then break");
+                None => {
+                    self.flat_end(position)?;
                     break;
-                } // Handle deserialization error
+                }
             }
         }
-        dbg!("returning none");
-        None
+        Ok(None)
     }
 
-    pub fn set(&self, key: &[u8], value: &[u8]) {
-        let kv = KeyValuePair {
-            key: key.to_vec(),
-            value: value.to_vec(),
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+    fn block_get(&self, key: &[u8], seq: u64) -> Result<Option<(u64, Value)>> {
+        let first = self
+            .blocks
+            .partition_point(|block| block.is_before(key, seq));
+        // The versions of a key may continue in the blocks after the first
+        for block in &self.blocks[first..] {
+            let mut entries = self
+                .read_data_block(block, self.verify_checksums)?
+                .seek(key);
+            for (found, value) in entries.by_ref() {
+                match found.user_key.as_ref().cmp(key) {
+                    Ordering::Equal if found.seq <= seq => return Ok(Some((found.seq, value))),
+                    Ordering::Greater => return Ok(None),
+                    _ => {}
+                }
+            }
+            if entries.is_malformed() {
+                return Err(self.corruption(block.offset as usize));
+            }
+        }
+        Ok(None)
+    }
+
+    fn may_contain_prefix(&self, prefix: &[u8]) -> Result<bool> {
+        match &self.prefix_filter {
+            Some(filter) if filter.extractor.prefix(prefix) == Some(prefix) => {
+                self.check_filter(&filter.filter, prefix)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Whether `filter` may hold `item`, reading the filter if it wasn't
+    /// yet.
+    fn check_filter(&self, filter: &Filter, item: &[u8]) -> Result<bool> {
+        let loaded = match (filter.filter.get(), filter.handle) {
+            (Some(loaded), _) => loaded,
+            (None, Some(handle)) => {
+                // Like the other blocks after the data, filters are always
+                // verified
+                let encoded = self.read_block(handle, true)?;
+                let opened = self.open_filter(encoded, handle.offset as usize)?;
+                filter.filter.get_or_init(|| opened)
+            }
+            (None, None) => return Ok(true),
         };
-        dbg!("data sent to write queue");
-        dbg!(self.write_queue.0.send(kv).unwrap());
-    }
-
-    fn write_batch(
-        mmap: &Arc<RwLock<MmapMut>>,
-        bloom_filter: &Arc<RwLock<Bloom<Vec<u8>>>>,
-        index: &Arc<RwLock<Vec<IndexEntry>>>,
-        batch: &[KeyValuePair],
-    ) {
-        println!("Starting batch write of {} items", batch.len());
-
-        // let mut file_guard = file.write();
-        let mut mmap_guard = mmap.write();
-        let mut bloom_filter_guard = bloom_filter.write();
-        let mut index_guard = index.write();
-
-        let current_position = mmap_guard.len();
-        // let current_position = match file_guard.seek(SeekFrom::End(0)) {
-        //     Ok(pos) => pos,
-        //     Err(e) => {
-        //         eprintln!("Error seeking to end of file: {:?}", e);
-        //         return;
-        //     }
-        // };
-        let mut buffer = Vec::new();
-
-        for (i, kv) in batch.iter().enumerate() {
-            let serialized = bincode::serialize(kv).unwrap();
-            // if let Err(e) = file_guard.write_all(&serialized) {
-            //     eprintln!("Error writing to file: {:?}", e);
-            //     continue;
-            // }
-            buffer.extend_from_slice(&serialized);
-            bloom_filter_guard.set(&kv.key);
-
-            if i % INDEX_INTERVAL == 0 {
-                index_guard.push(IndexEntry {
-                    key: kv.key.clone(),
-                    position: (current_position + buffer.len() - serialized.len()) as u64,
-                });
+        Ok(loaded.may_contain(item))
+    }
+
+    /// Decodes a filter of the table's policy stored at `offset`.
+    fn open_filter(&self, encoded: &[u8], offset: usize) -> Result<Box<dyn KeyFilter>> {
+        self.filter_policy
+            .open(encoded)
+            .ok_or_else(|| self.corruption(offset))
+    }
+
+    /// Bytes taken by the filters read so far.
+    fn filter_memory(&self) -> usize {
+        let prefix_filter = self.prefix_filter.as_ref().map(|prefix| &prefix.filter);
+        self.key_filter
+            .iter()
+            .chain(prefix_filter)
+            .filter_map(|filter| filter.filter.get())
+            .map(|loaded| loaded.memory_usage())
+            .sum()
+    }
+
+    /// Position to start reading a flat table at to find every version of
+    /// `key`: the last index entry before the key.
+    fn seek(&self, key: &[u8]) -> usize {
+        // An index entry equal to `key` may point past its newest versions
+        match self
+            .index
+            .partition_point(|entry| entry.key.as_slice() < key)
+        {
+            0 => self.format.data_start(self.mmap.len()),
+            before => self.index[before - 1].position as usize,
+        }
+    }
+
+    /// Collects the versions of the keys in `range` from a flat table.
+    fn flat_scan(&self, range: &KeyRange) -> Result<Vec<(InternalKey, Value)>> {
+        let mut position = match &range.0 {
+            Bound::Included(start) | Bound::Excluded(start) => self.seek(start),
+            Bound::Unbounded => self.format.data_start(self.mmap.len()),
+        };
+        let mut entries = Vec::new();
+        while position < self.mmap.len() {
+            match self.format.decode(&self.mmap[position..]) {
+                Some((entry, size)) => {
+                    position += size;
+                    if !before_end(range, &entry.0.user_key) {
+                        break;
+                    }
+                    if after_start(range, &entry.0.user_key) {
+                        entries.push(entry);
+                    }
+                }
+                None => {
+                    self.flat_end(position)?;
+                    break;
+                }
             }
         }
-        // if let Err(e) = file_guard.flush() {
-        //     eprintln!("Error flushing file: {:?}", e);
-        // }
-        mmap_guard.clone_from_slice(&buffer);
-        println!("Batch write completed successfully");
+        Ok(entries)
+    }
+
+    /// The data blocks that may hold versions of keys in `range`.
+    fn blocks_overlapping(&self, range: &KeyRange) -> std::ops::Range<usize> {
+        let first = match &range.0 {
+            Bound::Included(start) | Bound::Excluded(start) => self
+                .blocks
+                .partition_point(|block| block.last_key.as_slice() < start.as_ref()),
+            Bound::Unbounded => 0,
+        };
+        // The versions of the last key of a block may continue in the next
+        let end = match &range.1 {
+            Bound::Included(end) | Bound::Excluded(end) => {
+                let past = self
+                    .blocks
+                    .partition_point(|block| block.last_key.as_slice() <= end.as_ref());
+                (past + 1).min(self.blocks.len())
+            }
+            Bound::Unbounded => self.blocks.len(),
+        };
+        first..end.max(first)
+    }
+
+    /// Reads the versions of the keys in `range` from the data block at
+    /// `index`.
+    fn read_block_range(
+        &self,
+        index: usize,
+        range: &KeyRange,
+        verify: bool,
+    ) -> Result<VecDeque<(InternalKey, Value)>> {
+        let entry = &self.blocks[index];
+        let block = self.read_data_block(entry, verify)?;
+        let mut iter = block.iter();
+        let entries = iter
+            .by_ref()
+            .filter(|(key, _)| {
+                after_start(range, &key.user_key) && before_end(range, &key.user_key)
+            })
+            .collect();
+        if iter.is_malformed() {
+            return Err(self.corruption(entry.offset as usize));
+        }
+        Ok(entries)
+    }
+}
+
+/// Writes a table file, keeping track of the offset blocks end up at.
+struct TableWriter {
+    writer: BufWriter<File>,
+    offset: u64,
+}
+
+impl TableWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Writes `block` followed by its CRC32C, returning the handle of the
+    /// block alone.
+    fn write_block(&mut self, block: &[u8]) -> io::Result<BlockHandle> {
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: block.len() as u64,
+        };
+        self.write(block)?;
+        self.write(&crc32c::crc32c(block).to_le_bytes())?;
+        Ok(handle)
+    }
+
+    /// Writes a data block ending with `last`, returning its index entry.
+    fn write_data_block(
+        &mut self,
+        block: &[u8],
+        last: Option<&InternalKey>,
+    ) -> io::Result<BlockIndexEntry> {
+        let handle = self.write_block(block)?;
+        let last = last.expect("a data block holds at least one entry");
+        Ok(BlockIndexEntry {
+            last_key: last.user_key.to_vec(),
+            last_seq: last.seq,
+            offset: handle.offset,
+            size: handle.size,
+        })
+    }
+
+    fn write_serialized<T: Serialize>(&mut self, value: &T) -> io::Result<BlockHandle> {
+        self.write_block(&bincode::serialize(value).map_err(io::Error::other)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::key::MAX_SEQ;
+    use crate::kv::prefix::{DelimitedPrefix, FixedPrefix};
+    use crate::kv::testing::test_dir;
+
+    fn options() -> TableOptions {
+        TableOptions {
+            block_size: 128,
+            ..Default::default()
+        }
+    }
+
+    /// Writes a table holding `key-000` to `key-099`, at sequence numbers
+    /// 1 to 100.
+    fn create(name: &str) -> PathBuf {
+        let path = test_dir(name).join("000001.sst");
+        let entries = (0..100).map(|i| {
+            (
+                InternalKey::new(format!("key-{:03}", i).as_bytes(), i + 1),
+                Value::Put(Bytes::from("value")),
+            )
+        });
+        SST::create(&path, entries, &options()).unwrap();
+        path
+    }
+
+    /// Checks that `result` reports the block after the header as corrupt.
+    fn assert_corrupt<T: std::fmt::Debug>(result: Result<T>, path: &Path) {
+        match result {
+            Err(ShortDBErrors::Corruption { file, offset }) => {
+                assert_eq!(file, path);
+                assert_eq!(offset, FORMAT_HEADER.len() as u64);
+            }
+            other => panic!("expected corruption, got {:?}", other),
+        }
+    }
 
-        // Ensure that changes are flushed to disk
-        // mmap_guard.flush().unwrap();
+    fn flip_byte(path: &Path, offset: usize) {
+        let mut data = fs::read(path).unwrap();
+        data[offset] ^= 0xff;
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let path = create("sst-round-trip");
+        let sst = SST::new(&path, &options()).unwrap();
+        assert_eq!(sst.max_seq(), 100);
+        assert_eq!(
+            sst.get(b"key-042", MAX_SEQ).unwrap(),
+            Some((43, Value::Put(Bytes::from("value"))))
+        );
+        // Written after the read's sequence number
+        assert_eq!(sst.get(b"key-042", 42).unwrap(), None);
+        assert_eq!(sst.get(b"key-100", MAX_SEQ).unwrap(), None);
+
+        let range = (
+            Bound::Included(Bytes::from("key-010")),
+            Bound::Excluded(Bytes::from("key-020")),
+        );
+        let scanned: Vec<_> = sst.scan(&range).unwrap().map(Result::unwrap).collect();
+        assert_eq!(scanned.len(), 10);
+        assert_eq!(scanned[0].0.user_key, Bytes::from("key-010"));
+        let reversed: Vec<_> = sst
+            .scan(&range)
+            .unwrap()
+            .rev()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(reversed.into_iter().rev().collect::<Vec<_>>(), scanned);
+    }
+
+    #[test]
+    fn damaged_data_block_is_reported_as_corruption() {
+        let path = create("sst-corrupted-block");
+        // A byte of the first data block, right after the header
+        flip_byte(&path, FORMAT_HEADER.len() + 20);
+        let sst = SST::new(&path, &options()).unwrap();
+
+        assert_corrupt(sst.get(b"key-000", MAX_SEQ), &path);
+        assert_corrupt(sst.entries(), &path);
+        // Blocks after it are still read
+        assert!(sst.get(b"key-099", MAX_SEQ).unwrap().is_some());
+
+        // A scan fails once it reaches the block, and ends there
+        let mut scan = sst.scan(&(Bound::Unbounded, Bound::Unbounded)).unwrap();
+        assert_corrupt(scan.next().unwrap(), &path);
+        assert!(scan.next().is_none());
+
+        // Compaction checks them even when reads skip them
+        let unverified = TableOptions {
+            verify_checksums: false,
+            ..options()
+        };
+        let sst = SST::new(&path, &unverified).unwrap();
+        assert_corrupt(sst.entries(), &path);
+    }
+
+    #[test]
+    fn damaged_footer_fails_opening() {
+        let path = create("sst-corrupted-footer");
+        let len = fs::metadata(&path).unwrap().len() as usize;
+        flip_byte(&path, len - FOOTER_LEN);
+        assert!(matches!(
+            SST::new(&path, &options()),
+            Err(ShortDBErrors::Corruption { .. })
+        ));
+    }
+
+    /// A table holding the `name` and `email` fields of users 00 to 19,
+    /// keyed `user:<id>:<field>`.
+    fn user_table(name: &str, options: &TableOptions) -> PathBuf {
+        let path = test_dir(name).join("000001.sst");
+        let entries = (0..20).flat_map(|id| {
+            ["email", "name"].map(|field| {
+                let key = format!("user:{:02}:{}", id, field);
+                (
+                    InternalKey::new(key.as_bytes(), 1),
+                    Value::Put(Bytes::from("value")),
+                )
+            })
+        });
+        SST::create(&path, entries, options).unwrap();
+        path
+    }
+
+    fn with_extractor(extractor: impl PrefixExtractor + 'static) -> TableOptions {
+        TableOptions {
+            prefix_extractor: Some(Arc::new(extractor)),
+            ..Default::default()
+        }
+    }
+
+    /// Of the prefixes of users 20 to 119, none of which the table holds,
+    /// the number its prefix filter lets through.
+    fn absent_users_passed(sst: &SST) -> usize {
+        (20..120)
+            .filter(|id| {
+                let prefix = format!("user:{:02}:", id);
+                sst.may_contain_prefix(prefix.as_bytes()).unwrap()
+            })
+            .count()
+    }
+
+    const USER_PREFIX: DelimitedPrefix = DelimitedPrefix {
+        delimiter: b':',
+        count: 2,
+    };
+
+    #[test]
+    fn prefix_filter_rules_out_absent_prefixes() {
+        let options = with_extractor(USER_PREFIX);
+        let sst = SST::new(&user_table("sst-prefix-filter", &options), &options).unwrap();
+        for id in 0..20 {
+            let prefix = format!("user:{:02}:", id);
+            assert!(sst.may_contain_prefix(prefix.as_bytes()).unwrap());
+        }
+        assert!(absent_users_passed(&sst) < 10);
+        // Not a complete prefix, so the filter can't tell
+        assert!(sst.may_contain_prefix(b"user:").unwrap());
+        assert!(sst.may_contain_prefix(b"user:99").unwrap());
+    }
+
+    #[test]
+    fn prefix_filter_of_another_extractor_is_not_used() {
+        let path = user_table("sst-prefix-other-extractor", &with_extractor(USER_PREFIX));
+        // `user:99:` is a complete prefix of both
+        let sst = SST::new(&path, &with_extractor(FixedPrefix(8))).unwrap();
+        assert_eq!(absent_users_passed(&sst), 100);
+        let sst = SST::new(&path, &TableOptions::default()).unwrap();
+        assert_eq!(absent_users_passed(&sst), 100);
+    }
+
+    #[test]
+    fn key_filter_is_read_on_first_get() {
+        let options = TableOptions::default();
+        let sst = SST::new(&user_table("sst-lazy-filter", &options), &options).unwrap();
+        assert_eq!(sst.filter_memory(), 0);
+        assert!(sst.get(b"user:00:name", MAX_SEQ).unwrap().is_some());
+        assert!(sst.filter_memory() > 0);
+    }
+
+    #[test]
+    fn key_filter_size_follows_bits_per_key() {
+        let filter_memory = |bits_per_key| {
+            let options = TableOptions {
+                bloom_bits_per_key: bits_per_key,
+                ..Default::default()
+            };
+            let path = user_table(&format!("sst-filter-bits-{}", bits_per_key), &options);
+            let sst = SST::new(&path, &options).unwrap();
+            assert!(sst.get(b"user:00:name", MAX_SEQ).unwrap().is_some());
+            sst.filter_memory()
+        };
+        // 40 keys
+        assert_eq!(filter_memory(10), 50);
+        assert_eq!(filter_memory(20), 100);
+
+        // No filter at all with 0 bits per key
+        assert_eq!(filter_memory(0), 0);
+        let options = TableOptions {
+            bloom_bits_per_key: 0,
+            ..Default::default()
+        };
+        let sst = SST::new(&user_table("sst-no-filter", &options), &options).unwrap();
+        assert!(sst.reader.key_filter.is_none());
+        assert!(sst.get(b"user:19:email", MAX_SEQ).unwrap().is_some());
+        assert!(sst.get(b"user:20:email", MAX_SEQ).unwrap().is_none());
     }
 }
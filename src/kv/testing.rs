@@ -0,0 +1,28 @@
+use std::path::{Path, PathBuf};
+
+/// A fresh `test_db` directory for the test `name`, emptied of what an
+/// earlier run left there. Tests of one run get their own, so they can run
+/// in parallel.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("shorterdb-{}", std::process::id()))
+        .join(name)
+        .join("test_db");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Copies the files of `dir` as they are on disk now into a sibling
+/// directory, returning it: the database in `dir` as a crash would leave
+/// it, without anything `close` or `Drop` would still write.
+pub fn crash_copy(dir: &Path) -> PathBuf {
+    let crashed = dir.with_file_name("crashed");
+    let _ = std::fs::remove_dir_all(&crashed);
+    std::fs::create_dir_all(&crashed).unwrap();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, crashed.join(path.file_name().unwrap())).unwrap();
+    }
+    crashed
+}
@@ -0,0 +1,240 @@
+use super::{batch::WriteBatch, db::ShorterDB, snapshot::Snapshot, value::Value};
+use crate::errors::Result;
+use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A transaction, begun with `ShorterDB::begin_transaction` or
+/// `ShorterDB::begin_pessimistic_transaction`. Its writes stay buffered,
+/// and visible to its own reads, until `commit` applies them atomically.
+///
+/// An optimistic transaction reads the database as of its start and locks
+/// nothing: `commit` fails with `TransactionConflict` if a key read through
+/// `get_for_update`, or written, was written by anyone else since the
+/// transaction began.
+///
+/// A pessimistic transaction locks those keys instead, as it reaches them,
+/// and reads their latest state; nobody else can write them until it ends,
+/// so its commit cannot conflict. Writes outside it, transactional or not,
+/// wait for its locks as well. Taking a lock can fail with `Deadlock` or
+/// `LockTimeout`, after which the transaction should be rolled back.
+pub struct Transaction<'a> {
+    db: &'a ShorterDB,
+    mode: Mode,
+    writes: BTreeMap<Bytes, Value>,
+    /// Keys checked for conflicts at commit, or locked.
+    tracked: BTreeSet<Bytes>,
+}
+
+enum Mode {
+    /// Reads see the database as of the snapshot.
+    Optimistic(Snapshot),
+    /// Locks are held under the transaction's id in the lock manager.
+    Pessimistic(u64),
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn optimistic(db: &'a ShorterDB) -> Self {
+        Self::new(db, Mode::Optimistic(db.snapshot()))
+    }
+
+    pub(crate) fn pessimistic(db: &'a ShorterDB) -> Self {
+        Self::new(db, Mode::Pessimistic(db.locks().begin()))
+    }
+
+    fn new(db: &'a ShorterDB, mode: Mode) -> Self {
+        Self {
+            db,
+            mode,
+            writes: BTreeMap::new(),
+            tracked: BTreeSet::new(),
+        }
+    }
+
+    /// Looks `key` up, seeing the transaction's own writes. Like
+    /// `ShorterDB::get`, a deleted key is `Ok(None)` and a missing one
+    /// `Err(KeyNotFound)`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        match (self.writes.get(key), &self.mode) {
+            (Some(value), _) => Ok(value.as_put().cloned()),
+            (None, Mode::Optimistic(snapshot)) => self.db.get_at(key, snapshot),
+            (None, Mode::Pessimistic(_)) => self.db.get(key),
+        }
+    }
+
+    /// Looks `key` up like `get`, and keeps anyone else from writing the key
+    /// before the transaction commits.
+    pub fn get_for_update(&mut self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.track(key)?;
+        self.get(key)
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write(key, Value::Put(Bytes::copy_from_slice(value)))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.write(key, Value::Delete)
+    }
+
+    fn write(&mut self, key: &[u8], value: Value) -> Result<()> {
+        self.track(key)?;
+        self.writes.insert(Bytes::copy_from_slice(key), value);
+        Ok(())
+    }
+
+    fn track(&mut self, key: &[u8]) -> Result<()> {
+        if self.tracked.contains(key) {
+            return Ok(());
+        }
+        if let Mode::Pessimistic(id) = self.mode {
+            self.db.locks().lock(id, key)?;
+        }
+        self.tracked.insert(Bytes::copy_from_slice(key));
+        Ok(())
+    }
+
+    /// Applies the transaction's writes atomically, or fails and applies
+    /// none of them.
+    pub fn commit(self) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Value::Put(value) => batch.put(key, value),
+                Value::Delete => batch.delete(key),
+            }
+        }
+        match &self.mode {
+            Mode::Optimistic(snapshot) => self.db.write_if_unchanged(
+                &batch,
+                self.tracked.iter().map(|key| key.as_ref()),
+                snapshot.seq(),
+            ),
+            Mode::Pessimistic(_) => self.db.write_holding_locks(&batch),
+        }
+    }
+
+    /// Discards the transaction's writes; dropping it does the same.
+    pub fn rollback(self) {}
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if let Mode::Pessimistic(id) = self.mode {
+            self.db
+                .locks()
+                .unlock(id, self.tracked.iter().map(|key| key.as_ref()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ShortDBErrors;
+    use crate::kv::{db::ShorterDB, options::Options, testing::test_dir};
+    use bytes::Bytes;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn open(name: &str) -> ShorterDB {
+        let options = Options {
+            lock_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        ShorterDB::with_options(test_dir(name), options).unwrap()
+    }
+
+    #[test]
+    fn optimistic_commit_conflicts_with_a_newer_write() {
+        let db = open("optimistic-conflict");
+        db.set(b"x", b"0").unwrap();
+        let mut txn = db.begin_transaction();
+        assert_eq!(txn.get_for_update(b"x").unwrap(), Some(Bytes::from("0")));
+        txn.set(b"x", b"1").unwrap();
+        db.set(b"x", b"2").unwrap();
+        assert!(matches!(
+            txn.commit(),
+            Err(ShortDBErrors::TransactionConflict)
+        ));
+        assert_eq!(db.get(b"x").unwrap(), Some(Bytes::from("2")));
+    }
+
+    #[test]
+    fn optimistic_reads_ignore_later_writes() {
+        let db = open("optimistic-snapshot");
+        db.set(b"x", b"0").unwrap();
+        let mut txn = db.begin_transaction();
+        db.set(b"x", b"1").unwrap();
+        assert_eq!(txn.get(b"x").unwrap(), Some(Bytes::from("0")));
+        txn.set(b"y", b"1").unwrap();
+        assert_eq!(txn.get(b"y").unwrap(), Some(Bytes::from("1")));
+        txn.commit().unwrap();
+        assert_eq!(db.get(b"y").unwrap(), Some(Bytes::from("1")));
+    }
+
+    #[test]
+    fn plain_writes_wait_for_pessimistic_locks() {
+        let db = Arc::new(open("pessimistic-lost-update"));
+        db.set(b"y", b"0").unwrap();
+
+        let mut txn = db.begin_pessimistic_transaction();
+        txn.get_for_update(b"y").unwrap();
+        assert!(matches!(
+            db.set(b"y", b"plain"),
+            Err(ShortDBErrors::LockTimeout)
+        ));
+        let mut optimistic = db.begin_transaction();
+        optimistic.set(b"y", b"optimistic").unwrap();
+        assert!(matches!(
+            optimistic.commit(),
+            Err(ShortDBErrors::LockTimeout)
+        ));
+
+        // A write waiting for the lock goes through once the transaction
+        // commits, after its write
+        let writer = std::thread::spawn({
+            let db = Arc::clone(&db);
+            move || db.set(b"y", b"plain")
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        txn.set(b"y", b"txn").unwrap();
+        txn.commit().unwrap();
+        writer.join().unwrap().unwrap();
+        assert_eq!(db.get(b"y").unwrap(), Some(Bytes::from("plain")));
+    }
+
+    #[test]
+    fn pessimistic_deadlock_is_detected() {
+        let db = Arc::new(
+            ShorterDB::with_options(
+                test_dir("pessimistic-deadlock"),
+                Options {
+                    lock_timeout: Duration::from_secs(5),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        );
+        let mut first = db.begin_pessimistic_transaction();
+        first.set(b"a", b"1").unwrap();
+
+        let second = std::thread::spawn({
+            let db = Arc::clone(&db);
+            move || {
+                let mut second = db.begin_pessimistic_transaction();
+                second.set(b"b", b"2").unwrap();
+                // Waits for `first`, until it gives up on the deadlock
+                second.set(b"a", b"2")
+            }
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        let first_result = first.set(b"b", b"1");
+        drop(first);
+        let second_result = second.join().unwrap();
+        // Whichever closes the cycle fails, the other gets the lock once it
+        // is rolled back
+        assert!(
+            matches!(first_result, Err(ShortDBErrors::Deadlock))
+                || matches!(second_result, Err(ShortDBErrors::Deadlock))
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use bytes::Bytes;
+
+/// Value that versions before typed deletes stored for a deleted key.
+pub const LEGACY_TOMBSTONE: &[u8] = b"tombstone";
+
+/// What the latest write did to a key: store a value, or delete it.
+///
+/// Deletes are kept as tombstones in the memtables and SSTs so they keep
+/// hiding the older values of their key, until compaction drops them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Put(Bytes),
+    Delete,
+}
+
+impl Value {
+    /// Reads a value written before deletes were typed, where a delete was
+    /// stored as the value `b"tombstone"`.
+    pub fn from_legacy(value: Bytes) -> Self {
+        if value.as_ref() == LEGACY_TOMBSTONE {
+            Value::Delete
+        } else {
+            Value::Put(value)
+        }
+    }
+
+    /// The stored value, `None` for a delete.
+    pub fn as_put(&self) -> Option<&Bytes> {
+        match self {
+            Value::Put(value) => Some(value),
+            Value::Delete => None,
+        }
+    }
+
+    pub fn is_delete(&self) -> bool {
+        matches!(self, Value::Delete)
+    }
+
+    /// Bytes taken up by the value itself.
+    pub fn size(&self) -> usize {
+        self.as_put().map_or(0, |value| value.len())
+    }
+}
@@ -1,61 +1,730 @@
+use super::value::Value;
 use bytes::Bytes;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, Read, Write};
+use parking_lot::{Condvar, Mutex};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Starts every log of checksummed records, its last byte is the format
+/// version. Logs without it were written before deletes were typed, and
+/// store a delete as the value `b"tombstone"`.
+const FORMAT_HEADER: &[u8; 8] = b"SDBWAL\x00\x01";
+const MAGIC_LEN: usize = 7;
+
+/// The single log of versions before the WAL was split into segments.
+const LEGACY_LOG_FILE: &str = "wal.log";
+
+const RECORD_HEADER_LEN: usize = 8;
+
+const KIND_PUT: u8 = 0;
+const KIND_DELETE: u8 = 1;
+
+/// When writes are fsynced to the WAL, trading durability for throughput.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Every write is fsynced before it returns, and before readers see it.
+    Always,
+    /// The WAL is fsynced in the background every interval, and by a write
+    /// finding the last fsync older than that; a crash loses at most about
+    /// the last interval of writes. The interval can't be zero.
+    Periodic(Duration),
+    /// Every write returns once it is on disk, but concurrent writers share
+    /// one fsync. Readers may see a write shortly before it is durable.
+    GroupCommit,
+    /// Nothing is fsynced until the WAL is rotated, for bulk loads that can
+    /// be redone after a crash.
+    Disabled,
+}
+
+/// How recovery deals with damaged WAL records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// Any damaged record, even a torn tail, fails opening the database.
+    AbsoluteConsistency,
+    /// A record torn by a crash while it was written ends the log; any other
+    /// damage fails opening the database.
+    TolerateCorruptedTail,
+    /// Recovery stops at the first damaged record, leaving out everything
+    /// written after it: the database comes back as of some point in time.
+    PointInTime,
+    /// Damaged records are skipped, replaying every intact one.
+    SkipAnyCorruptedRecord,
+}
+
+/// What recovering the WAL replayed and left out.
+#[derive(Clone, Debug, Default)]
+pub struct RecoveryReport {
+    /// Number of WAL records replayed.
+    pub records_replayed: u64,
+    /// The stretches of WAL segments that were not replayed.
+    pub dropped: Vec<DroppedRange>,
+}
+
+impl RecoveryReport {
+    /// Whether every write in the WAL was recovered.
+    pub fn is_clean(&self) -> bool {
+        self.dropped.is_empty()
+    }
+}
+
+/// Bytes of a WAL segment recovery left out.
+#[derive(Clone, Debug)]
+pub struct DroppedRange {
+    pub segment: PathBuf,
+    pub offset: u64,
+    pub len: u64,
+    pub reason: DropReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// A record only partially written when the process died, at the end
+    /// of the last segment written to.
+    TornTail,
+    /// Bytes that aren't a valid record, with intact records or later
+    /// segments after them.
+    Corrupted,
+    /// Intact records after a damaged one, left out by `PointInTime`.
+    AfterCorruption,
+}
+
+/// A stretch of a log with its offset and length.
+enum Chunk {
+    Record(Vec<WALEntry>, u64, u64),
+    Damaged(u64, u64, DropReason),
+}
 
 pub struct WALEntry {
     pub key: Bytes,
-    pub value: Bytes,
+    pub value: Value,
+    /// Sequence number of the write, 0 in logs written before writes had
+    /// one.
+    pub seq: u64,
 }
 
+/// The write-ahead log, split into segments (`wal-000001.log`, ...) that
+/// are only ever appended to while they are the newest one.
+///
+/// A segment is sealed, and the next one started, when the memtable is
+/// frozen or the segment outgrows its size limit. Once every write in a
+/// segment has reached an SST, the MANIFEST records the first segment still
+/// needed and the older ones are removed.
 pub struct WAL {
-    path: PathBuf,
-    file: File,
+    dir: PathBuf,
+    /// Id of the segment being written.
+    id: u64,
+    /// Shared with writers fsyncing it outside the WAL lock.
+    file: Arc<File>,
+    /// Bytes in the segment being written.
+    size: u64,
+    max_segment_size: u64,
+    /// Number of records written, across rotations.
+    appended: u64,
 }
 
 impl WAL {
-    /// Creates a new WAL in a given directory.
-    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
-        let path = dir.as_ref().join("wal.log");
-        let file = OpenOptions::new().create(true).append(true).open(&path)?;
-        Ok(WAL { path, file })
-    }
-
-    /// Writes an entry to the WAL.
-    pub fn write(&mut self, entry: &WALEntry) -> io::Result<()> {
-        // Serialize the entry
-        self.file.write_all(&entry.key.len().to_le_bytes())?; // Key length
-        self.file.write_all(entry.key.as_ref())?; // Key
-        self.file.write_all(&entry.value.len().to_le_bytes())?; // Value length
-        self.file.write_all(entry.value.as_ref())?; // Value
-        self.file.flush()?; // Ensure data is written to disk
+    /// Opens the WAL in `dir`, writing to a fresh segment; the segments
+    /// already there are only read back by recovery.
+    ///
+    /// Segments older than `first_live` only hold writes that reached SSTs,
+    /// and are removed. A `wal.log` of versions before segments becomes the
+    /// newest of them.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        first_live: u64,
+        max_segment_size: u64,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        Self::remove_segments_before(&dir, first_live)?;
+        let mut last = Self::segments(&dir)?
+            .last()
+            .map_or(0, |(id, _)| *id)
+            .max(first_live.saturating_sub(1));
+
+        let legacy = dir.join(LEGACY_LOG_FILE);
+        if legacy.exists() {
+            last += 1;
+            fs::rename(&legacy, segment_path(&dir, last))?;
+        }
+
+        let file = Self::open_log(&segment_path(&dir, last + 1))?;
+        File::open(&dir)?.sync_all()?; // persist the new segment
+        Ok(WAL {
+            dir,
+            id: last + 1,
+            size: file.metadata()?.len(),
+            file: Arc::new(file),
+            max_segment_size,
+            appended: 0,
+        })
+    }
+
+    /// Opens the log at `path` for appending, starting it with the format
+    /// header if it is new.
+    fn open_log(path: &Path) -> io::Result<File> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(FORMAT_HEADER)?;
+            file.flush()?;
+        }
+        Ok(file)
+    }
+
+    /// Writes `entries` to the WAL as a single record: after a crash either
+    /// all of them are read back, or none.
+    ///
+    /// The record is handed to the OS, not fsynced; see `sync`.
+    pub fn write(&mut self, entries: &[WALEntry]) -> io::Result<()> {
+        let mut file = &*self.file;
+        self.size += Self::write_record(&mut file, entries)? as u64;
+        file.flush()?;
+        self.appended += 1;
+        if self.size >= self.max_segment_size {
+            self.rotate()?;
+        }
         Ok(())
     }
 
-    /// Reads all entries from the WAL.
-    pub fn read_entries(&self) -> io::Result<Vec<WALEntry>> {
-        let file = File::open(&self.path)?;
-        let mut reader = BufReader::new(file);
+    /// Fsyncs the records written so far.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Id of the segment being written.
+    pub fn current_segment(&self) -> u64 {
+        self.id
+    }
+
+    /// Number of records written so far, rotated logs included.
+    pub fn appended(&self) -> u64 {
+        self.appended
+    }
+
+    /// Records are framed as `[payload length: u32][CRC32C of the payload:
+    /// u32][payload]`, the payload holding the number of entries followed by
+    /// the entries themselves. Returns the length of the record.
+    fn write_record<W: Write>(writer: &mut W, entries: &[WALEntry]) -> io::Result<usize> {
+        let mut payload = Vec::new();
+        put_len(&mut payload, entries.len())?;
+        for entry in entries {
+            payload.extend_from_slice(&entry.seq.to_le_bytes());
+            put_len(&mut payload, entry.key.len())?;
+            payload.extend_from_slice(&entry.key);
+            match &entry.value {
+                Value::Put(value) => {
+                    payload.push(KIND_PUT);
+                    put_len(&mut payload, value.len())?;
+                    payload.extend_from_slice(value);
+                }
+                Value::Delete => payload.push(KIND_DELETE),
+            }
+        }
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        put_len(&mut record, payload.len())?;
+        record.extend_from_slice(&crc32c::crc32c(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+        writer.write_all(&record)?;
+        Ok(record.len())
+    }
+
+    /// Seals the segment being written and starts the next one, returning
+    /// its id: every write made so far lives in older segments.
+    pub fn rotate(&mut self) -> io::Result<u64> {
+        self.file.sync_all()?;
+        let id = self.id + 1;
+        self.file = Arc::new(Self::open_log(&segment_path(&self.dir, id))?);
+        File::open(&self.dir)?.sync_all()?; // persist the new segment
+        self.id = id;
+        self.size = FORMAT_HEADER.len() as u64;
+        Ok(id)
+    }
+
+    /// Lists the segments in `dir` with their ids, oldest first.
+    pub fn segments<P: AsRef<Path>>(dir: P) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(id) = Self::segment_id(&path) {
+                segments.push((id, path));
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    fn segment_id(path: &Path) -> Option<u64> {
+        let name = path.file_name()?.to_str()?;
+        name.strip_prefix("wal-")?
+            .strip_suffix(".log")?
+            .parse()
+            .ok()
+    }
+
+    /// The segments written before the WAL was opened, oldest first: the
+    /// writes recovery replays.
+    pub fn recovered_segments(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(Self::segments(&self.dir)?
+            .into_iter()
+            .filter(|(id, _)| *id < self.id)
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    /// Removes the segments of `dir` older than `id`.
+    pub fn remove_segments_before<P: AsRef<Path>>(dir: P, id: u64) -> io::Result<()> {
+        for (_, path) in Self::segments(&dir)?
+            .into_iter()
+            .take_while(|(segment, _)| *segment < id)
+        {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Reads back the writes of the segments written before the WAL was
+    /// opened, oldest first, dealing with damaged records as `mode` says and
+    /// noting what was left out in `report`.
+    pub fn recover(
+        &self,
+        mode: RecoveryMode,
+        report: &mut RecoveryReport,
+    ) -> io::Result<Vec<WALEntry>> {
+        let segments = self
+            .recovered_segments()?
+            .into_iter()
+            .map(|segment| Ok((Self::read_chunks(&segment)?, segment)))
+            .collect::<io::Result<Vec<_>>>()?;
+        // Only the end of the last segment holding anything can be torn by
+        // a crash; the segments after it were started but never written to
+        let last_written = segments.iter().rposition(|(chunks, _)| !chunks.is_empty());
+
         let mut entries = Vec::new();
+        let mut stopped = false;
+        for (i, (chunks, segment)) in segments.into_iter().enumerate() {
+            for chunk in chunks {
+                let (offset, len, reason) = match chunk {
+                    Chunk::Record(_, offset, len) | Chunk::Damaged(offset, len, _) if stopped => {
+                        (offset, len, DropReason::AfterCorruption)
+                    }
+                    Chunk::Record(record, _, _) => {
+                        entries.extend(record);
+                        report.records_replayed += 1;
+                        continue;
+                    }
+                    Chunk::Damaged(offset, len, DropReason::TornTail)
+                        if Some(i) != last_written =>
+                    {
+                        (offset, len, DropReason::Corrupted)
+                    }
+                    Chunk::Damaged(offset, len, reason) => (offset, len, reason),
+                };
+                let fatal = match mode {
+                    RecoveryMode::AbsoluteConsistency => true,
+                    RecoveryMode::TolerateCorruptedTail => reason == DropReason::Corrupted,
+                    RecoveryMode::PointInTime | RecoveryMode::SkipAnyCorruptedRecord => false,
+                };
+                if fatal {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("damaged WAL record in {:?} at offset {}", segment, offset),
+                    ));
+                }
+                stopped |= mode == RecoveryMode::PointInTime;
+                match report.dropped.last_mut() {
+                    // Everything after a stop is one stretch per segment
+                    Some(last)
+                        if reason == DropReason::AfterCorruption
+                            && last.reason == reason
+                            && last.segment == segment
+                            && last.offset + last.len == offset =>
+                    {
+                        last.len += len;
+                    }
+                    _ => report.dropped.push(DroppedRange {
+                        segment: segment.clone(),
+                        offset,
+                        len,
+                        reason,
+                    }),
+                }
+            }
+        }
+        Ok(entries)
+    }
 
-        let mut buffer = vec![0; 8]; // Buffer for lengths
+    /// Splits the log at `path` into its records and the stretches that
+    /// can't be read as records, with their offsets and lengths. Logs
+    /// without a header are read as well.
+    fn read_chunks(path: &Path) -> io::Result<Vec<Chunk>> {
+        let data = fs::read(path)?;
+        let (base, mut chunks) = match data.strip_prefix(&FORMAT_HEADER[..MAGIC_LEN]) {
+            Some([version, records @ ..]) if *version == FORMAT_HEADER[MAGIC_LEN] => {
+                (FORMAT_HEADER.len() as u64, Self::read_records(records))
+            }
+            Some([version, ..]) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported WAL format version {}", version),
+                ))
+            }
+            // A log torn while its header was written holds no entries yet
+            _ if FORMAT_HEADER.starts_with(&data) => return Ok(Vec::new()),
+            _ => (0, Self::read_unframed_entries(&data)),
+        };
+        for chunk in &mut chunks {
+            match chunk {
+                Chunk::Record(_, offset, _) | Chunk::Damaged(offset, _, _) => *offset += base,
+            }
+        }
+        Ok(chunks)
+    }
 
-        while let Ok(_) = reader.read_exact(&mut buffer) {
-            let key_len = usize::from_le_bytes(buffer[0..8].try_into().unwrap());
-            let mut key = vec![0; key_len];
-            reader.read_exact(&mut key)?;
+    /// Splits the checksummed records following the header.
+    ///
+    /// After a damaged record, reading resumes at the next offset a valid
+    /// record starts at. A damaged record that nothing valid follows is the
+    /// torn tail of the log when it runs up to or past its end, or when only
+    /// zeros follow it (file systems may extend a file before its data
+    /// reaches the disk). Anywhere else it means the log was corrupted.
+    fn read_records(records: &[u8]) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        while offset < records.len() {
+            let rest = &records[offset..];
+            if let Some((record, len)) = Self::decode_record(rest) {
+                chunks.push(Chunk::Record(record, offset as u64, len as u64));
+                offset += len;
+                continue;
+            }
 
-            reader.read_exact(&mut buffer)?;
-            let value_len = usize::from_le_bytes(buffer[0..8].try_into().unwrap());
-            let mut value = vec![0; value_len];
-            reader.read_exact(&mut value)?;
+            let skipped = (1..rest.len())
+                .find(|&skip| Self::decode_record(&rest[skip..]).is_some())
+                .unwrap_or(rest.len());
+            let torn = skipped == rest.len()
+                && (rest.iter().all(|&byte| byte == 0)
+                    || rest.len() < RECORD_HEADER_LEN
+                    || RECORD_HEADER_LEN
+                        + u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize
+                        >= rest.len());
+            let reason = match torn {
+                true => DropReason::TornTail,
+                false => DropReason::Corrupted,
+            };
+            chunks.push(Chunk::Damaged(offset as u64, skipped as u64, reason));
+            offset += skipped;
+        }
+        chunks
+    }
 
-            entries.push(WALEntry {
-                key: Bytes::from(key),
-                value: Bytes::from(value),
-            });
+    /// Decodes the record at the start of `data` along with its length,
+    /// `None` if it is incomplete, fails its checksum or is malformed.
+    fn decode_record(data: &[u8]) -> Option<(Vec<WALEntry>, usize)> {
+        let (len, crc) = data.get(..RECORD_HEADER_LEN)?.split_at(4);
+        let end = RECORD_HEADER_LEN + u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let payload = data.get(RECORD_HEADER_LEN..end)?;
+        if crc32c::crc32c(payload) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return None;
         }
+        Self::decode_payload(payload)
+            .ok()
+            .map(|entries| (entries, end))
+    }
 
+    fn decode_payload(mut payload: &[u8]) -> io::Result<Vec<WALEntry>> {
+        let count = read_u32(&mut payload)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let seq = read_u64(&mut payload)?;
+            let key = read_bytes(&mut payload)?;
+            let mut kind = [0; 1];
+            payload.read_exact(&mut kind)?;
+            let value = match kind[0] {
+                KIND_PUT => Value::Put(read_bytes(&mut payload)?),
+                KIND_DELETE => Value::Delete,
+                kind => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown WAL entry kind {}", kind),
+                    ))
+                }
+            };
+            entries.push(WALEntry { key, value, seq });
+        }
         Ok(entries)
     }
+
+    /// Splits a log written before entries were framed into records into
+    /// its entries, one record each. Without checksums nothing can be read
+    /// past a damaged entry.
+    fn read_unframed_entries(records: &[u8]) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        let mut reader = records;
+        loop {
+            let offset = (records.len() - reader.len()) as u64;
+            let reason = match Self::read_unframed_entry(&mut reader) {
+                Ok(Some(entry)) => {
+                    let len = records.len() as u64 - reader.len() as u64 - offset;
+                    chunks.push(Chunk::Record(vec![entry], offset, len));
+                    continue;
+                }
+                Ok(None) => break,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => DropReason::TornTail,
+                Err(_) => DropReason::Corrupted,
+            };
+            let len = records.len() as u64 - offset;
+            chunks.push(Chunk::Damaged(offset, len, reason));
+            break;
+        }
+        chunks
+    }
+
+    /// Reads an entry of a log written before entries were framed into
+    /// records.
+    fn read_unframed_entry<R: Read>(reader: &mut R) -> io::Result<Option<WALEntry>> {
+        let mut buffer = [0; 8]; // Buffer for lengths
+
+        if reader.read(&mut buffer[..1])? == 0 {
+            return Ok(None); // clean end of log
+        }
+        reader.read_exact(&mut buffer[1..])?;
+        let key_len = usize::from_le_bytes(buffer);
+        let mut key = vec![0; key_len];
+        reader.read_exact(&mut key)?;
+
+        reader.read_exact(&mut buffer)?;
+        let value_len = usize::from_le_bytes(buffer);
+        let mut value = vec![0; value_len];
+        reader.read_exact(&mut value)?;
+
+        Ok(Some(WALEntry {
+            key: Bytes::from(key),
+            value: Value::from_legacy(Bytes::from(value)),
+            seq: 0,
+        }))
+    }
+}
+
+/// Shares WAL fsyncs between writers: a writer waiting for its record to
+/// reach the disk either finds an fsync already covering it, or runs one
+/// covering every record written so far.
+pub struct GroupSync {
+    progress: Mutex<SyncProgress>,
+    /// Signalled whenever an fsync ends.
+    synced: Condvar,
+}
+
+struct SyncProgress {
+    /// Number of records known to be on disk.
+    synced: u64,
+    syncing: bool,
+    last_sync: Instant,
+}
+
+impl Default for GroupSync {
+    fn default() -> Self {
+        Self {
+            progress: Mutex::new(SyncProgress {
+                synced: 0,
+                syncing: false,
+                last_sync: Instant::now(),
+            }),
+            synced: Condvar::new(),
+        }
+    }
+}
+
+impl GroupSync {
+    /// Waits until the first `records` records written to `wal` are on disk.
+    pub fn sync_through(&self, records: u64, wal: &Mutex<WAL>) -> io::Result<()> {
+        let mut progress = self.progress.lock();
+        while progress.syncing {
+            if progress.synced >= records {
+                return Ok(());
+            }
+            self.synced.wait(&mut progress);
+        }
+        if progress.synced >= records {
+            return Ok(());
+        }
+        progress.syncing = true;
+        drop(progress);
+
+        // The WAL lock is only held to pick the file, writers go on while it
+        // is fsynced. Rotating the WAL fsyncs the old log, so the records in
+        // logs rotated away since are on disk as well.
+        let (file, appended) = {
+            let wal = wal.lock();
+            (Arc::clone(&wal.file), wal.appended)
+        };
+        let result = file.sync_data();
+
+        let mut progress = self.progress.lock();
+        progress.syncing = false;
+        if result.is_ok() {
+            progress.synced = progress.synced.max(appended);
+            progress.last_sync = Instant::now();
+        }
+        drop(progress);
+        self.synced.notify_all();
+        result
+    }
+
+    /// Whether the last fsync is more than `interval` old.
+    pub fn is_due(&self, interval: Duration) -> bool {
+        self.progress.lock().last_sync.elapsed() >= interval
+    }
+}
+
+/// Path of the WAL segment with the given id.
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("wal-{:06}.log", id))
+}
+
+fn put_len(buffer: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "WAL entry larger than 4 GiB"))?;
+    buffer.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+/// Reads a `u32` length followed by that many bytes.
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Bytes> {
+    let mut bytes = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(Bytes::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::testing::test_dir;
+
+    fn entry(key: &str, seq: u64) -> WALEntry {
+        WALEntry {
+            key: Bytes::from(key.to_string()),
+            value: Value::Put(Bytes::from_static(b"value")),
+            seq,
+        }
+    }
+
+    /// Writes three single entry records to a fresh WAL in `dir`, returning
+    /// the segment holding them and the offset each record starts at.
+    fn write_log(dir: &Path) -> (PathBuf, Vec<u64>) {
+        let mut wal = WAL::open(dir, 0, 1 << 20).unwrap();
+        let mut offsets = Vec::new();
+        for (seq, key) in ["a", "b", "c"].into_iter().enumerate() {
+            offsets.push(wal.size);
+            wal.write(&[entry(key, seq as u64 + 1)]).unwrap();
+        }
+        (segment_path(dir, wal.current_segment()), offsets)
+    }
+
+    fn recover(dir: &Path, mode: RecoveryMode) -> io::Result<(Vec<u64>, RecoveryReport)> {
+        let wal = WAL::open(dir, 0, 1 << 20)?;
+        let mut report = RecoveryReport::default();
+        let entries = wal.recover(mode, &mut report)?;
+        Ok((entries.iter().map(|entry| entry.seq).collect(), report))
+    }
+
+    fn reasons(report: &RecoveryReport) -> Vec<DropReason> {
+        report.dropped.iter().map(|range| range.reason).collect()
+    }
+
+    #[test]
+    fn recovers_every_intact_record() {
+        let dir = test_dir("wal-intact");
+        write_log(&dir);
+        let (seqs, report) = recover(&dir, RecoveryMode::AbsoluteConsistency).unwrap();
+        assert_eq!(seqs, [1, 2, 3]);
+        assert_eq!(report.records_replayed, 3);
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn truncated_last_record_is_a_torn_tail() {
+        let dir = test_dir("wal-torn");
+        let (segment, _) = write_log(&dir);
+        let len = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let (seqs, report) = recover(&dir, RecoveryMode::TolerateCorruptedTail).unwrap();
+        assert_eq!(seqs, [1, 2]);
+        assert_eq!(reasons(&report), [DropReason::TornTail]);
+    }
+
+    #[test]
+    fn zeros_after_the_last_record_are_a_torn_tail() {
+        let dir = test_dir("wal-zeros");
+        let (segment, _) = write_log(&dir);
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[0; 64]).unwrap();
+
+        let (seqs, report) = recover(&dir, RecoveryMode::TolerateCorruptedTail).unwrap();
+        assert_eq!(seqs, [1, 2, 3]);
+        assert_eq!(reasons(&report), [DropReason::TornTail]);
+    }
+
+    #[test]
+    fn damaged_record_before_intact_ones_is_corruption() {
+        let dir = test_dir("wal-corrupted");
+        let (segment, offsets) = write_log(&dir);
+        let mut data = fs::read(&segment).unwrap();
+        // A byte of the second record's payload
+        data[offsets[1] as usize + RECORD_HEADER_LEN + 2] ^= 0xff;
+        fs::write(&segment, data).unwrap();
+
+        let (seqs, report) = recover(&dir, RecoveryMode::SkipAnyCorruptedRecord).unwrap();
+        assert_eq!(seqs, [1, 3]);
+        assert_eq!(reasons(&report), [DropReason::Corrupted]);
+        assert_eq!(report.dropped[0].offset, offsets[1]);
+        assert!(recover(&dir, RecoveryMode::TolerateCorruptedTail).is_err());
+    }
+
+    #[test]
+    fn damaged_end_of_a_sealed_segment_is_corruption() {
+        let dir = test_dir("wal-sealed-torn");
+        let (segment, _) = write_log(&dir);
+        let mut wal = WAL::open(&dir, 0, 1 << 20).unwrap();
+        wal.write(&[entry("d", 4)]).unwrap();
+        drop(wal);
+        let len = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        // Replaying the next segment would leave a hole in the log
+        assert!(recover(&dir, RecoveryMode::TolerateCorruptedTail).is_err());
+        let (seqs, report) = recover(&dir, RecoveryMode::SkipAnyCorruptedRecord).unwrap();
+        assert_eq!(seqs, [1, 2, 4]);
+        assert_eq!(reasons(&report), [DropReason::Corrupted]);
+        let (seqs, report) = recover(&dir, RecoveryMode::PointInTime).unwrap();
+        assert_eq!(seqs, [1, 2]);
+        assert_eq!(
+            reasons(&report),
+            [DropReason::Corrupted, DropReason::AfterCorruption]
+        );
+    }
 }
@@ -66,7 +66,7 @@
 //         //                 Err(e) => println!("Some error hapend,{}", e),
 //         //             };
 //         //         }
-//         //         Some(Commands::Delete { key }) => match db.delete(&key.as_bytes()) {
+//         //         Some(Commands::Delete { key }) => match db.delete(key.as_bytes()) {
 //         //             Ok(()) => {
 //         //                 println!("Value for key: {} changed to tombstone", key);
 //         //             }
@@ -85,11 +85,14 @@ pub mod kv;
 use anyhow::Result; // Import Result from anyhow for better error handling
 use clap::{Parser, Subcommand};
 use csv::ReaderBuilder;
-use kv::db::{self, ShorterDB};
-use std::collections::HashMap;
+use kv::compaction::SizeTieredCompaction;
+use kv::db::ShorterDB;
+use kv::options::Options;
+use kv::wal::SyncMode;
 use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(name = "shortdb")]
@@ -107,7 +110,18 @@ enum Commands {
 }
 
 fn main() -> Result<()> {
-    let mut db = ShorterDB::new(Path::new("./test_db"))?;
+    // Bulk loading rewrites less data with size-tiered compaction
+    let options = Options {
+        compaction_strategy: Arc::new(SizeTieredCompaction::default()),
+        ..Default::default()
+    };
+    let db = ShorterDB::with_options(Path::new("./test_db"), options)?;
+    for dropped in &db.recovery_report().dropped {
+        println!(
+            "Recovery left out {} bytes of {:?} at offset {} ({:?})",
+            dropped.len, dropped.segment, dropped.offset, dropped.reason
+        );
+    }
 
     // Read data from CSV file
     let csv_file_path = PathBuf::from("data.csv");
@@ -120,12 +134,20 @@ fn main() -> Result<()> {
         if record.len() == 2 {
             let key = record.get(0).unwrap();
             let value = record.get(1).unwrap();
-            db.set(key.as_bytes(), value.as_bytes())?;
+            // The load can simply be rerun after a crash, no need to fsync
+            db.set_with_sync(key.as_bytes(), value.as_bytes(), SyncMode::Disabled)?;
             println!("Inserted Key: {}, Value: {}", key, value);
             println!("{} keys inserted, {}", i, i % 256);
             i += 1;
         }
     }
+    let stats = db.compaction_stats();
+    println!(
+        "{} compaction: {} compactions, write amplification {:.2}",
+        stats.strategy,
+        stats.compactions,
+        stats.write_amplification()
+    );
 
     println!("Welcome to the ShortDB REPL!");
     println!("Syntax:- \n (i) set <key> <value> : maps <key> and <value> \n ");
@@ -167,7 +189,7 @@ fn main() -> Result<()> {
                     Err(e) => println!("Some error happened, {}", e),
                 };
             }
-            Some(Commands::Delete { key }) => match db.delete(&key.as_bytes()) {
+            Some(Commands::Delete { key }) => match db.delete(key.as_bytes()) {
                 Ok(()) => {
                     println!("Value for key: {} changed to tombstone", key);
                 }
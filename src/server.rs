@@ -1,20 +1,137 @@
+use parking_lot::Mutex;
 use proto::basic_server::{Basic, BasicServer};
-use proto::{GetRequest, GetResponse, SetRequest, SetResponse};
+use proto::{
+    BeginTransactionRequest, BeginTransactionResponse, GetRequest, GetResponse, SetRequest,
+    SetResponse, TransactionDelRequest, TransactionGetRequest, TransactionRequest,
+    TransactionSetRequest,
+};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
 use tonic::transport::Server;
 
 pub mod errors;
 pub mod kv;
+use errors::ShortDBErrors;
 use kv::db::ShorterDB;
+use kv::options::Options;
+use kv::transaction::Transaction;
+use kv::wal::SyncMode;
 
 mod proto {
     tonic::include_proto!("commands");
 }
 
+/// How long a transaction may go without a request before it is rolled
+/// back, releasing its locks; clients that went away leave theirs open.
+const TRANSACTION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 struct DbOperations {
-    db: Arc<Mutex<ShorterDB>>, // Add ShorterDB to the struct
+    db: &'static ShorterDB, // Add ShorterDB to the struct
+    /// Open transactions by id.
+    transactions: Arc<Mutex<HashMap<u64, OpenTransaction>>>,
+    next_transaction: AtomicU64,
+}
+
+struct OpenTransaction {
+    transaction: Transaction<'static>,
+    /// When the last request on the transaction ended.
+    last_used: Instant,
+}
+
+impl OpenTransaction {
+    fn new(transaction: Transaction<'static>) -> Self {
+        Self {
+            transaction,
+            last_used: Instant::now(),
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.last_used.elapsed() >= TRANSACTION_IDLE_TIMEOUT
+    }
+}
+
+impl DbOperations {
+    /// Runs `op` on the transaction `id` on a blocking thread, as it may wait
+    /// for locks. The transaction leaves the table meanwhile, so the lock
+    /// is only held for the lookup.
+    async fn with_transaction<T, F>(&self, id: u64, op: F) -> Result<T, tonic::Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Transaction<'static>) -> errors::Result<T> + Send + 'static,
+    {
+        let mut transaction = self
+            .take_transaction(id)
+            .ok_or_else(transaction_not_found)?;
+        let (transaction, result) = tokio::task::spawn_blocking(move || {
+            let result = op(&mut transaction);
+            (transaction, result)
+        })
+        .await
+        .map_err(|_| tonic::Status::internal("Transaction operation panicked"))?;
+        self.transactions
+            .lock()
+            .insert(id, OpenTransaction::new(transaction));
+        result.map_err(to_status)
+    }
+
+    fn take_transaction(&self, id: u64) -> Option<Transaction<'static>> {
+        self.transactions
+            .lock()
+            .remove(&id)
+            .map(|open| open.transaction)
+    }
+
+    /// Rolls back the transactions left idle for longer than
+    /// `TRANSACTION_IDLE_TIMEOUT`, checking every few seconds. Transactions
+    /// busy with a request are out of the table and never rolled back.
+    fn start_transaction_reaper(&self) {
+        let transactions = Arc::clone(&self.transactions);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TRANSACTION_IDLE_TIMEOUT / 10);
+            loop {
+                interval.tick().await;
+                // Dropped outside the table lock, releasing their row locks
+                let expired: Vec<_> = {
+                    let mut transactions = transactions.lock();
+                    let idle: Vec<u64> = transactions
+                        .iter()
+                        .filter(|(_, open)| open.is_idle())
+                        .map(|(id, _)| *id)
+                        .collect();
+                    idle.iter()
+                        .filter_map(|id| transactions.remove(id))
+                        .collect()
+                };
+                drop(expired);
+            }
+        });
+    }
+}
+
+fn to_status(error: ShortDBErrors) -> tonic::Status {
+    match error {
+        ShortDBErrors::KeyNotFound => tonic::Status::not_found("Key not found"),
+        // The client is expected to retry these
+        ShortDBErrors::TransactionConflict | ShortDBErrors::Deadlock => {
+            tonic::Status::aborted(error.to_string())
+        }
+        ShortDBErrors::LockTimeout => tonic::Status::deadline_exceeded(error.to_string()),
+        ShortDBErrors::Corruption { .. } => tonic::Status::data_loss(error.to_string()),
+        ShortDBErrors::Closed => tonic::Status::unavailable(error.to_string()),
+        _ => tonic::Status::internal("Error accessing the database"),
+    }
+}
+
+fn transaction_not_found() -> tonic::Status {
+    tonic::Status::not_found("Transaction not found")
+}
+
+fn set_response() -> tonic::Response<SetResponse> {
+    tonic::Response::new(SetResponse { success: true })
 }
 
 #[tonic::async_trait]
@@ -23,41 +140,127 @@ impl Basic for DbOperations {
         &self,
         request: tonic::Request<GetRequest>,
     ) -> Result<tonic::Response<GetResponse>, tonic::Status> {
-        let key = request.get_ref().key.clone();
-
-        // Lock the database and call the `get` function
-        let db = self.db.lock().await; // Async lock the database
-        match db.get(key.as_bytes()) {
-            Ok(Some(value)) => match std::str::from_utf8(&value) {
-                Ok(string_value) => {
-                    let response = GetResponse {
-                        value: string_value.to_string(),
-                    };
-                    Ok(tonic::Response::new(response))
-                }
-                Err(_) => Err(tonic::Status::internal("Invalid UTF-8 sequence")),
-            },
-            Ok(None) => Err(tonic::Status::not_found("Key not found")),
-            Err(_) => Err(tonic::Status::internal("Error reading from the database")),
-        }
+        let key = request.into_inner().key;
+        let db = self.db;
+
+        // Reads may go to disk, off the runtime's threads
+        let value = tokio::task::spawn_blocking(move || db.get(key.as_bytes()))
+            .await
+            .map_err(|_| tonic::Status::internal("Get panicked"))?
+            .map_err(to_status)?
+            .ok_or_else(|| tonic::Status::not_found("Key not found"))?;
+        let value = String::from_utf8(value.to_vec())
+            .map_err(|_| tonic::Status::internal("Invalid UTF-8 sequence"))?;
+        Ok(tonic::Response::new(GetResponse { value }))
     }
 
     async fn set(
         &self,
         request: tonic::Request<SetRequest>,
     ) -> Result<tonic::Response<SetResponse>, tonic::Status> {
-        let key = request.get_ref().key.clone();
-        let value = request.get_ref().value.clone();
-
-        // Lock the database and call the `set` function
-        let mut db = self.db.lock().await; // Async lock the database
-        match db.set(key.as_bytes(), value.as_bytes()) {
-            Ok(_) => {
-                let response = SetResponse { success: true };
-                Ok(tonic::Response::new(response))
-            }
-            Err(_) => Err(tonic::Status::internal("Error writing to the database")),
-        }
+        let SetRequest { key, value } = request.into_inner();
+        let db = self.db;
+
+        // Waits for the group commit's fsync, and for row locks
+        tokio::task::spawn_blocking(move || db.set(key.as_bytes(), value.as_bytes()))
+            .await
+            .map_err(|_| tonic::Status::internal("Set panicked"))?
+            .map_err(to_status)?;
+        Ok(set_response())
+    }
+
+    async fn begin_transaction(
+        &self,
+        request: tonic::Request<BeginTransactionRequest>,
+    ) -> Result<tonic::Response<BeginTransactionResponse>, tonic::Status> {
+        let transaction = if request.get_ref().pessimistic {
+            self.db.begin_pessimistic_transaction()
+        } else {
+            self.db.begin_transaction()
+        };
+        let transaction_id = self.next_transaction.fetch_add(1, Ordering::Relaxed);
+        self.transactions
+            .lock()
+            .insert(transaction_id, OpenTransaction::new(transaction));
+        Ok(tonic::Response::new(BeginTransactionResponse {
+            transaction_id,
+        }))
+    }
+
+    async fn transaction_get(
+        &self,
+        request: tonic::Request<TransactionGetRequest>,
+    ) -> Result<tonic::Response<GetResponse>, tonic::Status> {
+        let TransactionGetRequest {
+            transaction_id,
+            key,
+            for_update,
+        } = request.into_inner();
+        let value = self
+            .with_transaction(transaction_id, move |transaction| match for_update {
+                true => transaction.get_for_update(key.as_bytes()),
+                false => transaction.get(key.as_bytes()),
+            })
+            .await?
+            .ok_or_else(|| tonic::Status::not_found("Key not found"))?;
+        let value = String::from_utf8(value.to_vec())
+            .map_err(|_| tonic::Status::internal("Invalid UTF-8 sequence"))?;
+        Ok(tonic::Response::new(GetResponse { value }))
+    }
+
+    async fn transaction_set(
+        &self,
+        request: tonic::Request<TransactionSetRequest>,
+    ) -> Result<tonic::Response<SetResponse>, tonic::Status> {
+        let TransactionSetRequest {
+            transaction_id,
+            key,
+            value,
+        } = request.into_inner();
+        self.with_transaction(transaction_id, move |transaction| {
+            transaction.set(key.as_bytes(), value.as_bytes())
+        })
+        .await?;
+        Ok(set_response())
+    }
+
+    async fn transaction_delete(
+        &self,
+        request: tonic::Request<TransactionDelRequest>,
+    ) -> Result<tonic::Response<SetResponse>, tonic::Status> {
+        let TransactionDelRequest {
+            transaction_id,
+            key,
+        } = request.into_inner();
+        self.with_transaction(transaction_id, move |transaction| {
+            transaction.delete(key.as_bytes())
+        })
+        .await?;
+        Ok(set_response())
+    }
+
+    async fn commit(
+        &self,
+        request: tonic::Request<TransactionRequest>,
+    ) -> Result<tonic::Response<SetResponse>, tonic::Status> {
+        let transaction = self
+            .take_transaction(request.get_ref().transaction_id)
+            .ok_or_else(transaction_not_found)?;
+        tokio::task::spawn_blocking(move || transaction.commit())
+            .await
+            .map_err(|_| tonic::Status::internal("Commit panicked"))?
+            .map_err(to_status)?;
+        Ok(set_response())
+    }
+
+    async fn rollback(
+        &self,
+        request: tonic::Request<TransactionRequest>,
+    ) -> Result<tonic::Response<SetResponse>, tonic::Status> {
+        self.take_transaction(request.get_ref().transaction_id)
+            .ok_or_else(transaction_not_found)?
+            .rollback();
+        Ok(set_response())
     }
 }
 
@@ -65,17 +268,64 @@ impl Basic for DbOperations {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "[::1]:50051".parse()?;
 
-    // Initialize the ShorterDB instance wrapped in Arc and Mutex
-    let db = Arc::new(Mutex::new(ShorterDB::new(Path::new("./test_db"))?));
+    // Concurrent requests share their fsyncs
+    let options = Options {
+        sync_mode: SyncMode::GroupCommit,
+        ..Default::default()
+    };
+    // Initialize the ShorterDB instance; it lives as long as the server, and
+    // the open transactions borrow it. It is never dropped, so it is closed
+    // explicitly once the server stops
+    let db: &'static ShorterDB = Box::leak(Box::new(ShorterDB::with_options(
+        Path::new("./test_db"),
+        options,
+    )?));
 
     // Pass the database to DbOperations
-    let db_operations = DbOperations { db };
+    let db_operations = DbOperations {
+        db,
+        transactions: Arc::new(Mutex::new(HashMap::new())),
+        next_transaction: AtomicU64::new(1),
+    };
+    db_operations.start_transaction_reaper();
 
     Server::builder()
         .layer(tower_http::cors::CorsLayer::permissive())
         .add_service(BasicServer::new(db_operations))
-        .serve(addr)
+        .serve_with_shutdown(addr, shutdown_signal())
         .await?;
 
+    tokio::task::spawn_blocking(|| db.close()).await??;
     Ok(())
 }
+
+/// Completes on Ctrl-C, or on SIGTERM where there is one.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Error listening for Ctrl-C: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Error listening for SIGTERM: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    println!("Shutting down");
+}